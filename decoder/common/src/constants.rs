@@ -10,20 +10,112 @@ pub const LEN_ASCON_NONCE: usize = 16;
 pub const LEN_ASCON_TAG: usize = 16;
 pub const LEN_ASCON_AEAD_OVERHEAD: usize = LEN_ASCON_NONCE + LEN_ASCON_TAG;
 
+// Attestation constants
+pub const LEN_DECODER_ID: usize = 4;
+pub const LEN_ATTESTATION_KEY: usize = 32;
+pub const LEN_ATTESTATION_NONCE: usize = 16;
+pub const LEN_ATTESTATION_MAC: usize = 32;
+pub const LEN_FIRMWARE_MEASUREMENT: usize = 32;
+pub const LEN_ATTESTATION_RESPONSE: usize =
+    LEN_DECODER_ID + LEN_FIRMWARE_MEASUREMENT + LEN_ATTESTATION_MAC;
+
+// Framing constants
+pub const LEN_HEADER_CHECKSUM: usize = 2;
+pub const LEN_BLOCK_CHECKSUM: usize = 4;
+pub const MAX_RETRANSMITS: usize = 3;
+
+// Baud rate constants
+pub const LEN_BAUD_RATE: usize = 4;
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+pub const SUPPORTED_BAUD_RATES: [u32; 4] = [115_200, 230_400, 460_800, 921_600];
+
+// Session constants
+pub const LEN_SESSION_NONCE: usize = 16;
+pub const LEN_SESSION_KEY: usize = 32;
+pub const LEN_RESPONSE_MAC: usize = 16;
+pub const LEN_X25519_KEY: usize = 32;
+pub const LEN_IDENTITY_KEY: usize = LEN_X25519_KEY;
+pub const LEN_CONNECT_FLAGS: usize = 1;
+pub const LEN_CONNECT_REQUEST: usize = LEN_CONNECT_FLAGS + LEN_SESSION_NONCE + LEN_X25519_KEY;
+pub const LEN_CONNECT_RESPONSE: usize = LEN_CONNECT_FLAGS + LEN_SESSION_NONCE + LEN_X25519_KEY;
+pub const CONNECT_FLAG_RESPONSE_AUTH: u8 = 0x01;
+pub const CONNECT_FLAG_ENCRYPTION: u8 = 0x02;
+pub const CONNECT_FLAG_CHECKSUM: u8 = 0x04;
+pub const SESSION_DIRECTION_TO_DECODER: u8 = 0x00;
+pub const SESSION_DIRECTION_TO_HOST: u8 = 0x01;
+
 // Secrets constants
 pub const LEN_BASE_CHANNEL_SECRET: usize = 32;
 pub const LEN_BASE_SUBSCRIPTION_SECRET: usize = 32;
 pub const LEN_CHANNEL_SECRET: usize = 32;
 
 // Update subscription constants
-pub const LEN_SUBSCRIPTION_INFO: usize = LEN_CHANNEL_ID + 2 * LEN_TIMESTAMP;
+pub const LEN_NUM_WINDOWS: usize = 1;
+pub const LEN_TIME_WINDOW: usize = 2 * LEN_TIMESTAMP;
+pub const MAX_SUBSCRIPTION_WINDOWS: usize = 4;
+pub const LEN_SUBSCRIPTION_INFO: usize =
+    LEN_CHANNEL_ID + LEN_NUM_WINDOWS + MAX_SUBSCRIPTION_WINDOWS * LEN_TIME_WINDOW;
 pub const LEN_STORED_SUBSCRIPTION: usize = LEN_SUBSCRIPTION_INFO + LEN_CHANNEL_SECRET;
-pub const LEN_ENCRYPTED_SUBSCRIPTION: usize = LEN_STORED_SUBSCRIPTION + LEN_ASCON_AEAD_OVERHEAD;
+pub const LEN_MERGE_POLICY: usize = 4;
+pub const LEN_SUBSCRIPTION_UPDATE: usize = LEN_MERGE_POLICY + LEN_STORED_SUBSCRIPTION;
+pub const LEN_ENCRYPTED_SUBSCRIPTION: usize = LEN_SUBSCRIPTION_UPDATE + LEN_ASCON_AEAD_OVERHEAD;
+
+// Factory reset constants
+pub const FACTORY_RESET_MAGIC: [u8; 8] = *b"FACRESET";
+pub const LEN_RESET_COUNTER: usize = 8;
+pub const LEN_FACTORY_RESET: usize = FACTORY_RESET_MAGIC.len() + LEN_DECODER_ID + LEN_RESET_COUNTER;
+pub const MAX_FACTORY_RESETS: u32 = FLASH_PAGE_SIZE / 16 - 1; // One 16-byte record per accepted reset after the header
+pub const LEN_ENCRYPTED_FACTORY_RESET: usize = LEN_FACTORY_RESET + LEN_ASCON_AEAD_OVERHEAD;
 
 // List subscription constants
 pub const EMERGENCY_CHANNEL_ID: u32 = 0x0;
 pub const LEN_STANDARD_CHANNELS: usize = 8;
-pub const LEN_SUBSCRIPTION_INFO_LIST: usize = 4 + LEN_STANDARD_CHANNELS * LEN_SUBSCRIPTION_INFO; // The 4 accounts for the 32-bit "number of channels" requirement in host tools
+pub const LEN_LIST_ENTRY: usize = LEN_CHANNEL_ID + LEN_TIME_WINDOW; // Each time window is reported as its own (channel, start, end) entry
+pub const LEN_LIST_STATUS: usize = 1; // Optional per-entry status byte, see LIST_FLAG_STATUS
+pub const LIST_FLAG_STATUS: u8 = 0x01; // List request flag asking for a status byte after every entry
+pub const LIST_STATUS_EXPIRED: u8 = 0x01; // Status bit set when the entry ends before the last frame decoded since boot
+pub const LEN_SUBSCRIPTION_INFO_LIST: usize =
+    4 + LEN_STANDARD_CHANNELS * MAX_SUBSCRIPTION_WINDOWS * (LEN_LIST_ENTRY + LEN_LIST_STATUS); // The 4 accounts for the 32-bit "number of channels" requirement in host tools
+
+// Authentication failure constants. The count persists across resets and is only cleared by an
+// authenticated factory reset, so a legitimate decoder does not pay back-off forever for old
+// failures. Successful decodes never clear it, as captured frames can be replayed between
+// forgeries. A decoder which is locked out cannot authenticate anything, so it stays locked out.
+pub const AUTH_FAILURE_GRACE: u32 = 8; // Failures allowed before any back-off
+pub const AUTH_FAILURE_BASE_DELAY_MS: u32 = 100; // Back-off after the first failure past the grace count, doubling with each one after
+pub const AUTH_FAILURE_MAX_DELAY_MS: u32 = 5_000;
+pub const AUTH_FAILURE_LOCKOUT: Option<u32> = None; // Failures after which the decoder permanently refuses to decrypt, if set
+pub const MAX_AUTH_FAILURES: u32 = FLASH_PAGE_SIZE / 16 - 1; // One 16-byte record per failure after the header, where the count saturates
+const _: () = assert!(
+    match AUTH_FAILURE_LOCKOUT {
+        Some(lockout) => lockout <= MAX_AUTH_FAILURES,
+        None => true,
+    },
+    "AUTH_FAILURE_LOCKOUT must fit in the failure record page"
+);
+
+// Tamper detection constants
+pub const TAMPER_LOCKOUT: u32 = 3; // Detected faults after which the decoder refuses to decrypt until it is re-provisioned
+pub const MAX_TAMPER_EVENTS: u32 = FLASH_PAGE_SIZE / 16 - 2; // One 16-byte record per fault after the header and provisioning ID, where the count saturates
+pub const LEN_PROVISIONING_ID: usize = 16;
+const _: () = assert!(
+    TAMPER_LOCKOUT > 0 && TAMPER_LOCKOUT <= MAX_TAMPER_EVENTS,
+    "TAMPER_LOCKOUT must fit in the tamper record page"
+);
+
+// Crash log constants
+pub const LEN_CRASH_FILE: usize = 24; // Trailing bytes of the source path of each panic location
+pub const LEN_CRASH_RECORD: usize = 4 + 4 + LEN_CRASH_FILE; // Line, column and source path
+pub const MAX_CRASH_RECORDS: u32 = (FLASH_PAGE_SIZE - 16) / LEN_CRASH_RECORD as u32; // Records after the header, where recording stops
+pub const CRASH_LOG_REPORTED_RECORDS: usize = 7; // Most recent records sent in a crash log response
+
+// Telemetry constants
+pub const TELEMETRY_CHANNELS: usize = LEN_STANDARD_CHANNELS + 1; // Every standard channel plus the emergency channel
+pub const LEN_TELEMETRY_CHANNEL: usize = LEN_CHANNEL_ID + 4;
+pub const NUM_FRAME_REJECTION_REASONS: usize = 5;
+pub const LEN_TELEMETRY: usize =
+    4 + TELEMETRY_CHANNELS * LEN_TELEMETRY_CHANNEL + 4 + NUM_FRAME_REJECTION_REASONS * 4 + 4 * 4;
+pub const TELEMETRY_FLAG_RESET: u8 = 0x01; // Telemetry request flag asking for the counters to be reset after reporting
 
 // Frame and picture constants
 pub const LEN_PICTURE_LEN: usize = 1;
@@ -32,6 +124,11 @@ pub const LEN_ENCRYPTED_PICTURE: usize = MAX_LEN_PICTURE + LEN_ASCON_AEAD_OVERHE
 pub const LEN_DECRYPTED_FRAME: usize =
     LEN_ENCRYPTED_PICTURE + LEN_CHANNEL_ID + LEN_TIMESTAMP + LEN_PICTURE_LEN;
 pub const LEN_ENCRYPTED_FRAME: usize = LEN_DECRYPTED_FRAME + LEN_ASCON_AEAD_OVERHEAD;
+pub const DECODE_TIME_BUDGET_US: Option<u32> = Some(5_000); // If set, every frame takes at least this long to decode, whichever check fails
+pub const MAX_BATCH_FRAMES: usize = 7; // As many frames as fit in one message with an Ascon tag
+pub const LEN_BATCH_ENTRY_HEADER: usize = 2; // Status byte and picture length before each decoded picture
+pub const BATCH_STATUS_OK: u8 = 0x00;
+pub const BATCH_STATUS_ERROR: u8 = 0x01;
 
 // Flash constants
 pub const FLASH_PAGE_SIZE: u32 = 0x2000;
@@ -40,13 +137,44 @@ pub const FLASH_FIRMWARE_SIZE: u32 = 28 * FLASH_PAGE_SIZE;
 pub const FLASH_FIRMWARE_CODE_SIZE: u32 = 25 * FLASH_PAGE_SIZE;
 
 pub const FLASH_OFFSET_RANDOM_BYTES: u32 = 25 * FLASH_PAGE_SIZE;
+pub const FLASH_OFFSET_PROVISIONING_ID: u32 = FLASH_OFFSET_RANDOM_BYTES + 2 * LEN_RNG_SEED as u32; // Random bytes after the RNG seeds, which change with every firmware build
 pub const FLASH_OFFSET_FRAME_KEY: u32 = 26 * FLASH_PAGE_SIZE;
 pub const FLASH_OFFSET_SUBSCRIPTION_KEY: u32 = FLASH_OFFSET_FRAME_KEY + LEN_ASCON_KEY as u32;
+pub const FLASH_OFFSET_ATTESTATION_KEY: u32 = FLASH_OFFSET_SUBSCRIPTION_KEY + LEN_ASCON_KEY as u32;
+pub const FLASH_OFFSET_DECODER_ID: u32 = FLASH_OFFSET_ATTESTATION_KEY + LEN_ATTESTATION_KEY as u32;
+pub const FLASH_OFFSET_IDENTITY_KEY: u32 = FLASH_OFFSET_DECODER_ID + LEN_DECODER_ID as u32;
+pub const FLASH_OFFSET_FIRMWARE_MEASUREMENT: u32 =
+    FLASH_OFFSET_IDENTITY_KEY + LEN_IDENTITY_KEY as u32;
 pub const FLASH_OFFSET_SUBSCRIPTION_BASE: u32 = 27 * FLASH_PAGE_SIZE;
 
+pub const FLASH_OFFSET_AUTH_FAILURES: u32 =
+    FLASH_OFFSET_SUBSCRIPTION_BASE + (LEN_STANDARD_CHANNELS as u32 + 1) * FLASH_PAGE_SIZE;
+pub const FLASH_OFFSET_TAMPER_EVENTS: u32 = FLASH_OFFSET_AUTH_FAILURES + FLASH_PAGE_SIZE;
+pub const FLASH_OFFSET_CRASH_LOG: u32 = FLASH_OFFSET_TAMPER_EVENTS + FLASH_PAGE_SIZE;
+pub const FLASH_OFFSET_RESET_COUNTER: u32 = FLASH_OFFSET_CRASH_LOG + FLASH_PAGE_SIZE;
+pub const FLASH_STATE_SIZE: u32 = 4 * FLASH_PAGE_SIZE; // Must match the STATE region in memory.x, which starts with the failure page
+const _: () = assert!(
+    FLASH_OFFSET_RESET_COUNTER + FLASH_PAGE_SIZE == FLASH_OFFSET_AUTH_FAILURES + FLASH_STATE_SIZE,
+    "The pages after the subscriptions must exactly fill the STATE region"
+);
+
 pub const FLASH_MAGIC_SUBSCRIPTION: u8 = 0x53;
+pub const FLASH_MAGIC_AUTH_FAILURES: u8 = 0x46;
+pub const FLASH_MAGIC_TAMPER_EVENTS: u8 = 0x54;
+pub const FLASH_MAGIC_CRASH_LOG: u8 = 0x50;
+pub const FLASH_MAGIC_RESET_COUNTER: u8 = 0x52;
 
 pub const FLASH_ADDR_RANDOM_BYTES: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_RANDOM_BYTES;
+pub const FLASH_ADDR_PROVISIONING_ID: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_PROVISIONING_ID;
 pub const FLASH_ADDR_FRAME_KEY: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_FRAME_KEY;
 pub const FLASH_ADDR_SUBSCRIPTION_KEY: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_SUBSCRIPTION_KEY;
+pub const FLASH_ADDR_ATTESTATION_KEY: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_ATTESTATION_KEY;
+pub const FLASH_ADDR_DECODER_ID: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_DECODER_ID;
+pub const FLASH_ADDR_IDENTITY_KEY: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_IDENTITY_KEY;
+pub const FLASH_ADDR_FIRMWARE_MEASUREMENT: u32 =
+    FLASH_ADDR_BASE + FLASH_OFFSET_FIRMWARE_MEASUREMENT;
 pub const FLASH_ADDR_SUBSCRIPTION_BASE: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_SUBSCRIPTION_BASE;
+pub const FLASH_ADDR_AUTH_FAILURES: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_AUTH_FAILURES;
+pub const FLASH_ADDR_TAMPER_EVENTS: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_TAMPER_EVENTS;
+pub const FLASH_ADDR_CRASH_LOG: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_CRASH_LOG;
+pub const FLASH_ADDR_RESET_COUNTER: u32 = FLASH_ADDR_BASE + FLASH_OFFSET_RESET_COUNTER;
@@ -1,8 +1,10 @@
 use crate::constants::*;
 use crate::{
-    BaseChannelSecret, BaseSubscriptionSecret, ChannelSecret, PictureKey, SubscriptionKey,
+    AttestationKey, AttestationMac, AttestationNonce, BaseChannelSecret, BaseSubscriptionSecret,
+    ChannelSecret, FirmwareMeasurement, IdentityKey, PictureKey, ResponseMac, SessionCipherKey,
+    SessionKey, SessionNonce, SubscriptionKey,
 };
-use tiny_keccak::{Hasher, Kmac};
+use tiny_keccak::{Hasher, Kmac, Sha3};
 
 pub fn derive_channel_secret(
     base_channel_secret: &BaseChannelSecret,
@@ -34,3 +36,108 @@ pub fn derive_picture_key(channel_secret: &ChannelSecret, timestamp: u64) -> Pic
     kmac.finalize(&mut picture_key);
     PictureKey(picture_key)
 }
+
+pub fn derive_attestation_key(
+    base_subscription_secret: &BaseSubscriptionSecret,
+    decoder_id: u32,
+) -> AttestationKey {
+    let mut kmac = Kmac::v256(&base_subscription_secret.0, b"derive_attestation_key");
+    kmac.update(&decoder_id.to_le_bytes());
+    let mut attestation_key = [0u8; LEN_ATTESTATION_KEY];
+    kmac.finalize(&mut attestation_key);
+    AttestationKey(attestation_key)
+}
+
+/// Computes the MAC a decoder returns in response to an attestation challenge.
+pub fn compute_attestation_mac(
+    attestation_key: &AttestationKey,
+    nonce: &AttestationNonce,
+    decoder_id: u32,
+    measurement: &FirmwareMeasurement,
+) -> AttestationMac {
+    let mut kmac = Kmac::v256(&attestation_key.0, b"attestation");
+    kmac.update(&nonce.0);
+    kmac.update(&decoder_id.to_le_bytes());
+    kmac.update(&measurement.0);
+    let mut mac = [0u8; LEN_ATTESTATION_MAC];
+    kmac.finalize(&mut mac);
+    AttestationMac(mac)
+}
+
+/// Measures the given firmware code region.
+pub fn measure_firmware(code: &[u8]) -> FirmwareMeasurement {
+    let mut hasher = Sha3::v256();
+    hasher.update(code);
+    let mut measurement = [0u8; LEN_FIRMWARE_MEASUREMENT];
+    hasher.finalize(&mut measurement);
+    FirmwareMeasurement(measurement)
+}
+
+/// Derives the X25519 private key a decoder uses to prove its identity during an encrypted
+/// session handshake.
+pub fn derive_identity_key(
+    base_subscription_secret: &BaseSubscriptionSecret,
+    decoder_id: u32,
+) -> IdentityKey {
+    let mut kmac = Kmac::v256(&base_subscription_secret.0, b"derive_identity_key");
+    kmac.update(&decoder_id.to_le_bytes());
+    let mut identity_key = [0u8; LEN_IDENTITY_KEY];
+    kmac.finalize(&mut identity_key);
+    IdentityKey(identity_key)
+}
+
+/// Derives the key for a host session from the requested flags, both parties' nonces and any
+/// key exchange output. The attestation key is used as the long-term secret, as both the
+/// decoder and the host (from the deployment secrets) can derive it. The Connect request is not
+/// authenticated, so binding its flags means a tampered request gives mismatched keys.
+pub fn derive_session_key(
+    attestation_key: &AttestationKey,
+    requested_flags: u8,
+    host_nonce: &SessionNonce,
+    decoder_nonce: &SessionNonce,
+    key_exchange: &[u8],
+) -> SessionKey {
+    let mut kmac = Kmac::v256(&attestation_key.0, b"derive_session_key");
+    kmac.update(&[requested_flags]);
+    kmac.update(&host_nonce.0);
+    kmac.update(&decoder_nonce.0);
+    kmac.update(key_exchange);
+    let mut session_key = [0u8; LEN_SESSION_KEY];
+    kmac.finalize(&mut session_key);
+    SessionKey(session_key)
+}
+
+/// Computes the MAC appended to a decoder response while response authentication is enabled.
+pub fn compute_response_mac(
+    session_key: &SessionKey,
+    sequence: u32,
+    opcode: u8,
+    data: &[u8],
+) -> ResponseMac {
+    let mut kmac = Kmac::v128(&session_key.0, b"response_mac");
+    kmac.update(&sequence.to_le_bytes());
+    kmac.update(&[opcode]);
+    kmac.update(&(data.len() as u16).to_le_bytes());
+    kmac.update(data);
+    let mut mac = [0u8; LEN_RESPONSE_MAC];
+    kmac.finalize(&mut mac);
+    ResponseMac(mac)
+}
+
+/// Derives the Ascon key used to encrypt message bodies in an encrypted host session.
+pub fn derive_session_cipher_key(session_key: &SessionKey) -> SessionCipherKey {
+    let kmac = Kmac::v128(&session_key.0, b"session_cipher_key");
+    let mut cipher_key = [0u8; LEN_ASCON_KEY];
+    kmac.finalize(&mut cipher_key);
+    SessionCipherKey(cipher_key)
+}
+
+/// Builds the Ascon nonce for a message body in an encrypted host session. Nonces are never
+/// sent, as both sides track the sequence number for each direction.
+pub fn make_session_nonce(direction: u8, opcode: u8, sequence: u32) -> [u8; LEN_ASCON_NONCE] {
+    let mut nonce = [0u8; LEN_ASCON_NONCE];
+    nonce[0] = direction;
+    nonce[1] = opcode;
+    nonce[4..8].copy_from_slice(&sequence.to_le_bytes());
+    nonce
+}
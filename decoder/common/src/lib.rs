@@ -1,6 +1,7 @@
 #![cfg_attr(not(test), no_std)]
 // #![no_std]
 
+pub mod checksum;
 pub mod constants;
 pub mod crypto;
 
@@ -20,14 +21,23 @@ pub const BINCODE_CONFIG: Configuration<LittleEndian, Fixint> = bincode::config:
     .with_fixed_int_encoding();
 
 /// Messages that the host sends to the decoder.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Zeroize)]
 pub enum MessageToDecoder {
-    ListSubscriptions,
+    ListSubscriptions { flags: u8 },
     UpdateSubscription(EncryptedSubscription),
+    FactoryReset(EncryptedFactoryReset),
     DecodeFrame(EncryptedFrame),
+    DecodeBatch(EncryptedFrameBatch),
+    Attest(AttestationNonce),
+    Connect(ConnectRequest),
+    SetBaudRate(u32),
+    Telemetry { flags: u8 },
+    CrashLog,
 }
 
 /// Messages that the decoder can send to the host.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Zeroize, ZeroizeOnDrop)]
 pub enum MessageFromDecoder {
     ListSubscriptions(SubscriptionInfoList),
@@ -63,6 +73,64 @@ pub struct PictureKey(pub [u8; LEN_ASCON_KEY]);
 #[serde(transparent)]
 pub struct SubscriptionKey(pub [u8; LEN_ASCON_KEY]);
 
+/// The Attestation Key which is derived for a particular device and used to answer
+/// attestation challenges.
+#[derive(Debug, Deserialize, Serialize, Decode, Encode, Zeroize, ZeroizeOnDrop)]
+#[serde(transparent)]
+pub struct AttestationKey(pub [u8; LEN_ATTESTATION_KEY]);
+
+/// The challenge sent by the host when requesting attestation.
+#[derive(Debug, Decode, Encode, Zeroize)]
+pub struct AttestationNonce(pub [u8; LEN_ATTESTATION_NONCE]);
+
+/// The MAC over an attestation challenge, decoder ID, and firmware measurement.
+#[derive(Debug, PartialEq, Eq, Decode, Encode, Zeroize)]
+pub struct AttestationMac(pub [u8; LEN_ATTESTATION_MAC]);
+
+/// A hash of the firmware code region.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Decode, Encode, Zeroize)]
+pub struct FirmwareMeasurement(pub [u8; LEN_FIRMWARE_MEASUREMENT]);
+
+/// The decoder's answer to an attestation challenge.
+#[derive(Debug, Decode, Encode)]
+pub struct AttestationResponse {
+    pub decoder_id: u32,
+    pub measurement: FirmwareMeasurement,
+    pub mac: AttestationMac,
+}
+
+/// A random nonce contributed by either side when establishing a host session.
+#[derive(Debug, Decode, Encode, Zeroize)]
+pub struct SessionNonce(pub [u8; LEN_SESSION_NONCE]);
+
+/// The key for a host session, derived from both session nonces.
+#[derive(Debug, Zeroize, ZeroizeOnDrop)]
+pub struct SessionKey(pub [u8; LEN_SESSION_KEY]);
+
+/// The Ascon key for message bodies in an encrypted host session.
+#[derive(Debug, Zeroize, ZeroizeOnDrop)]
+pub struct SessionCipherKey(pub [u8; LEN_ASCON_KEY]);
+
+/// The X25519 private key which identifies a decoder in an encrypted session handshake.
+#[derive(Debug, Zeroize, ZeroizeOnDrop)]
+pub struct IdentityKey(pub [u8; LEN_IDENTITY_KEY]);
+
+/// An X25519 public key exchanged during an encrypted session handshake.
+#[derive(Debug, Decode, Encode, Zeroize)]
+pub struct X25519PublicKey(pub [u8; LEN_X25519_KEY]);
+
+/// The MAC appended to a decoder response while response authentication is enabled.
+#[derive(Debug, PartialEq, Eq, Zeroize)]
+pub struct ResponseMac(pub [u8; LEN_RESPONSE_MAC]);
+
+/// The request sent by the host to negotiate optional protocol features for a session.
+#[derive(Debug, Decode, Encode, Zeroize)]
+pub struct ConnectRequest {
+    pub flags: u8,
+    pub host_nonce: SessionNonce,
+    pub host_public_key: X25519PublicKey,
+}
+
 #[derive(Debug, Deserialize, Serialize, Zeroize, ZeroizeOnDrop)]
 pub struct DeploymentSecrets {
     pub frame_key: FrameKey,
@@ -74,13 +142,58 @@ pub struct DeploymentSecrets {
 #[derive(Debug, Decode, Encode, Zeroize, ZeroizeOnDrop)]
 pub struct EncryptedSubscription(pub [u8; LEN_ENCRYPTED_SUBSCRIPTION]);
 
+/// A single time window of a subscription. Both `start` and `end` are inclusive.
+#[derive(Debug, Default, Copy, Clone, Deserialize, Serialize, Decode, Encode, Zeroize)]
+pub struct TimeWindow {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl TimeWindow {
+    /// Returns true if the given timestamp falls within this window.
+    pub fn contains(&self, timestamp: u64) -> bool {
+        timestamp >= self.start && timestamp <= self.end
+    }
+}
+
 /// Public information about a subscription. Embedded within a StoredSubscription and primarily
 /// used for serialization when communicating with the host.
-#[derive(Debug, Copy, Clone, Deserialize, Serialize, Decode, Encode, Zeroize)]
+#[derive(Debug, Default, Copy, Clone, Deserialize, Serialize, Decode, Encode, Zeroize)]
 pub struct SubscriptionInfo {
     pub channel_id: u32,
-    pub start: u64,
-    pub end: u64,
+    pub num_windows: u8,
+    pub windows: [TimeWindow; MAX_SUBSCRIPTION_WINDOWS],
+}
+
+impl SubscriptionInfo {
+    /// Returns the time windows which are in use.
+    pub fn windows(&self) -> &[TimeWindow] {
+        let num_windows = core::cmp::min(self.num_windows as usize, MAX_SUBSCRIPTION_WINDOWS);
+        &self.windows[..num_windows]
+    }
+
+    /// Returns true if every window of the subscription ends before the given timestamp.
+    pub fn is_expired(&self, timestamp: u64) -> bool {
+        self.windows().iter().all(|w| w.end < timestamp)
+    }
+
+    /// Returns true if the subscription has at least one window, and all windows are
+    /// well-formed, sorted, and disjoint.
+    pub fn is_valid(&self) -> bool {
+        if self.num_windows == 0 || self.num_windows as usize > MAX_SUBSCRIPTION_WINDOWS {
+            return false;
+        }
+        let windows = self.windows();
+        for i in 0..windows.len() {
+            if windows[i].start > windows[i].end {
+                return false;
+            }
+            if i > 0 && windows[i - 1].end >= windows[i].start {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 /// All information about a subscription.
@@ -90,7 +203,43 @@ pub struct StoredSubscription {
     pub channel_secret: ChannelSecret,
 }
 
+/// How a subscription update is combined with an existing subscription for the same channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Decode, Encode, Zeroize)]
+pub enum MergePolicy {
+    /// Overwrite the existing subscription with the update.
+    Replace,
+    /// Keep the union of the existing and updated time windows.
+    ExtendUnion,
+    /// Reject the update if any of its time windows overlap an existing window, otherwise keep
+    /// the union of both.
+    RejectOnOverlap,
+}
+
+/// The subscription update payload, which is encrypted before being sent to the decoder.
+#[derive(Debug, Decode, Encode, Zeroize, ZeroizeOnDrop)]
+pub struct SubscriptionUpdate {
+    pub policy: MergePolicy,
+    pub subscription: StoredSubscription,
+}
+
+/// A request to erase all standard channel subscriptions from a particular decoder. The decoder
+/// only accepts a counter higher than that of every reset it accepted before, so a captured
+/// request cannot be replayed.
+#[derive(Debug, Decode, Encode)]
+pub struct FactoryReset {
+    pub magic: [u8; FACTORY_RESET_MAGIC.len()],
+    pub decoder_id: u32,
+    pub counter: u64,
+}
+
+/// The factory reset payload received from the host, encrypted with the subscription key.
+#[derive(Debug, Decode, Encode, Zeroize, ZeroizeOnDrop)]
+pub struct EncryptedFactoryReset(pub [u8; LEN_ENCRYPTED_FACTORY_RESET]);
+
 /// A list of 8 optional SubscriptionInfo objects for each channel.
+///
+/// On the wire, every time window is sent as a separate (channel, start, end) entry, preceded
+/// by the total number of entries.
 #[derive(Debug, Zeroize)]
 pub struct SubscriptionInfoList {
     pub num_sub_channels: u32,
@@ -99,9 +248,14 @@ pub struct SubscriptionInfoList {
 
 impl Encode for SubscriptionInfoList {
     fn encode<E: Encoder>(&self, encoder: &mut E) -> core::result::Result<(), EncodeError> {
-        Encode::encode(&self.num_sub_channels, encoder)?;
-        for i in 0..self.num_sub_channels {
-            Encode::encode(&self.subscriptions[i as usize], encoder)?;
+        let subscriptions = &self.subscriptions[..self.num_sub_channels as usize];
+        let num_entries: u32 = subscriptions.iter().map(|s| s.windows().len() as u32).sum();
+        Encode::encode(&num_entries, encoder)?;
+        for sub in subscriptions {
+            for window in sub.windows() {
+                Encode::encode(&sub.channel_id, encoder)?;
+                Encode::encode(window, encoder)?;
+            }
         }
         Ok(())
     }
@@ -110,15 +264,34 @@ impl Encode for SubscriptionInfoList {
 impl Decode for SubscriptionInfoList {
     fn decode<D: Decoder>(decoder: &mut D) -> core::result::Result<Self, DecodeError> {
         let mut out = SubscriptionInfoList {
-            num_sub_channels: Decode::decode(decoder)?,
-            subscriptions: core::array::from_fn(|_| SubscriptionInfo {
-                channel_id: 0,
-                start: 0,
-                end: 0,
-            }),
+            num_sub_channels: 0,
+            subscriptions: core::array::from_fn(|_| SubscriptionInfo::default()),
         };
-        for i in 0..out.num_sub_channels {
-            out.subscriptions[i as usize] = Decode::decode(decoder)?;
+        let num_entries: u32 = Decode::decode(decoder)?;
+        for _ in 0..num_entries {
+            let channel_id: u32 = Decode::decode(decoder)?;
+            let window: TimeWindow = Decode::decode(decoder)?;
+            // Consecutive entries for the same channel are windows of the same subscription
+            let n = out.num_sub_channels as usize;
+            if n > 0
+                && out.subscriptions[n - 1].channel_id == channel_id
+                && (out.subscriptions[n - 1].num_windows as usize) < MAX_SUBSCRIPTION_WINDOWS
+            {
+                let sub = &mut out.subscriptions[n - 1];
+                sub.windows[sub.num_windows as usize] = window;
+                sub.num_windows += 1;
+                continue;
+            }
+            if n >= LEN_STANDARD_CHANNELS {
+                return Err(DecodeError::ArrayLengthMismatch {
+                    required: LEN_STANDARD_CHANNELS,
+                    found: n + 1,
+                });
+            }
+            out.subscriptions[n].channel_id = channel_id;
+            out.subscriptions[n].windows[0] = window;
+            out.subscriptions[n].num_windows = 1;
+            out.num_sub_channels += 1;
         }
         Ok(out)
     }
@@ -137,6 +310,33 @@ pub struct StoredSubscriptionList {
 #[derive(Debug, Decode, Encode, Zeroize, ZeroizeOnDrop)]
 pub struct EncryptedFrame(pub [u8; LEN_ENCRYPTED_FRAME]);
 
+/// Several frame payloads received from the host in one message, to be decoded in order.
+#[derive(Debug, Zeroize, ZeroizeOnDrop)]
+pub struct EncryptedFrameBatch {
+    pub num_frames: u8,
+    pub frames: [EncryptedFrame; MAX_BATCH_FRAMES],
+}
+
+impl EncryptedFrameBatch {
+    pub fn new() -> Self {
+        Self {
+            num_frames: 0,
+            frames: core::array::from_fn(|_| EncryptedFrame([0u8; LEN_ENCRYPTED_FRAME])),
+        }
+    }
+
+    /// Returns the frames which were received.
+    pub fn frames(&self) -> &[EncryptedFrame] {
+        &self.frames[..core::cmp::min(self.num_frames as usize, MAX_BATCH_FRAMES)]
+    }
+}
+
+impl Default for EncryptedFrameBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Encrypted frame data, stored in a DecryptedFrame object.
 #[derive(Debug, Decode, Encode, Zeroize, ZeroizeOnDrop)]
 pub struct EncryptedPicture(pub [u8; LEN_ENCRYPTED_PICTURE]);
@@ -165,6 +365,62 @@ pub struct SizedPicture {
 #[derive(Debug)]
 pub struct Timestamp(pub u64);
 
+/// The number of frames decoded on one channel.
+#[derive(Debug, Default, Copy, Clone, Decode, Encode)]
+pub struct ChannelFrameCount {
+    pub channel_id: u32,
+    pub frames: u32,
+}
+
+/// The number of frames rejected for each reason.
+#[derive(Debug, Default, Copy, Clone, Decode, Encode)]
+pub struct FrameRejections {
+    pub invalid_frame: u32,
+    pub no_subscription: u32,
+    pub outside_window: u32,
+    pub stale_timestamp: u32,
+    pub invalid_picture: u32,
+}
+
+/// Counters describing the health of a decoder since they were last reset.
+#[derive(Debug, Default, Copy, Clone, Decode, Encode)]
+pub struct Telemetry {
+    pub num_channels: u32,
+    pub frames_decoded: [ChannelFrameCount; TELEMETRY_CHANNELS],
+    /// Frames decoded on channels after all entries of `frames_decoded` were in use.
+    pub frames_decoded_untracked: u32,
+    pub frames_rejected: FrameRejections,
+    pub subscriptions_accepted: u32,
+    pub subscriptions_rejected: u32,
+    /// Stored subscriptions which failed their integrity checks when read.
+    pub integrity_failures: u32,
+    pub rng_reseeds: u32,
+}
+
+impl Telemetry {
+    pub const fn new() -> Self {
+        Self {
+            num_channels: 0,
+            frames_decoded: [ChannelFrameCount {
+                channel_id: 0,
+                frames: 0,
+            }; TELEMETRY_CHANNELS],
+            frames_decoded_untracked: 0,
+            frames_rejected: FrameRejections {
+                invalid_frame: 0,
+                no_subscription: 0,
+                outside_window: 0,
+                stale_timestamp: 0,
+                invalid_picture: 0,
+            },
+            subscriptions_accepted: 0,
+            subscriptions_rejected: 0,
+            integrity_failures: 0,
+            rng_reseeds: 0,
+        }
+    }
+}
+
 /// Returns true if the given 16 bytes are the complement of the given 16 bytes.
 pub fn check_complement_16b(a: &[u8; 16], b: &[u8; 16]) -> bool {
     for i in 0..16 {
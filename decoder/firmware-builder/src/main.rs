@@ -1,6 +1,9 @@
 use clap::Parser;
 use common::constants::*;
-use common::crypto::{derive_channel_secret, derive_subscription_key};
+use common::crypto::{
+    derive_attestation_key, derive_channel_secret, derive_identity_key, derive_subscription_key,
+    measure_firmware,
+};
 use common::{make_complement_16b, DeploymentSecrets};
 use rand::Rng;
 use std::fs::File;
@@ -95,6 +98,33 @@ fn main() -> std::io::Result<()> {
     let subscription_key_end = subscription_key_start + LEN_ASCON_KEY;
     output_firmware[subscription_key_start..subscription_key_end]
         .copy_from_slice(&subscription_key.0);
+    // Derive attestation key from secrets
+    let attestation_key =
+        derive_attestation_key(&secrets.base_subscription_secret, args.decoder_id);
+    // Write attestation key to firmware
+    let attestation_key_start = FLASH_OFFSET_ATTESTATION_KEY as usize;
+    let attestation_key_end = attestation_key_start + LEN_ATTESTATION_KEY;
+    output_firmware[attestation_key_start..attestation_key_end].copy_from_slice(&attestation_key.0);
+    // Write decoder ID to firmware
+    let decoder_id_start = FLASH_OFFSET_DECODER_ID as usize;
+    let decoder_id_end = decoder_id_start + LEN_DECODER_ID;
+    output_firmware[decoder_id_start..decoder_id_end]
+        .copy_from_slice(&args.decoder_id.to_le_bytes());
+
+    // Derive identity key from secrets
+    let identity_key = derive_identity_key(&secrets.base_subscription_secret, args.decoder_id);
+    // Write identity key to firmware
+    let identity_key_start = FLASH_OFFSET_IDENTITY_KEY as usize;
+    let identity_key_end = identity_key_start + LEN_IDENTITY_KEY;
+    output_firmware[identity_key_start..identity_key_end].copy_from_slice(&identity_key.0);
+
+    // Measure the code region, including the random padding after the code, so the decoder can
+    // check it is intact at boot
+    let measurement = measure_firmware(&output_firmware[..FLASH_FIRMWARE_CODE_SIZE as usize]);
+    // Write measurement to firmware
+    let measurement_start = FLASH_OFFSET_FIRMWARE_MEASUREMENT as usize;
+    let measurement_end = measurement_start + LEN_FIRMWARE_MEASUREMENT;
+    output_firmware[measurement_start..measurement_end].copy_from_slice(&measurement.0);
 
     // Set up channel 0 subscription
     let c0_id = EMERGENCY_CHANNEL_ID;
@@ -102,7 +132,7 @@ fn main() -> std::io::Result<()> {
     let c0_start: u64 = 0;
     let c0_end: u64 = u64::MAX;
 
-    let mut sub_bytes = [0u8; 128];
+    let mut sub_bytes = [0u8; 160];
 
     // Write the header
     let mut header_bytes = [FLASH_MAGIC_SUBSCRIPTION; 16];
@@ -111,12 +141,13 @@ fn main() -> std::io::Result<()> {
     sub_bytes[0..16].copy_from_slice(&header_bytes);
     sub_bytes[16..32].copy_from_slice(&make_complement_16b(&header_bytes));
 
-    // Write the timestamps
-    let mut timestamp_bytes = [0u8; 16];
-    timestamp_bytes[0..8].copy_from_slice(&c0_start.to_le_bytes());
-    timestamp_bytes[8..16].copy_from_slice(&c0_end.to_le_bytes());
-    sub_bytes[32..48].copy_from_slice(&timestamp_bytes);
-    sub_bytes[48..64].copy_from_slice(&make_complement_16b(&timestamp_bytes));
+    // Write the number of windows
+    let mut num_windows_bytes = [0u8; 16];
+    for i in 0..4 {
+        num_windows_bytes[i * 4..(i + 1) * 4].copy_from_slice(&1u32.to_le_bytes());
+    }
+    sub_bytes[32..48].copy_from_slice(&num_windows_bytes);
+    sub_bytes[48..64].copy_from_slice(&make_complement_16b(&num_windows_bytes));
 
     // Write the channel secret
     let mut channel_secret_bytes_1 = [0u8; 16];
@@ -128,9 +159,16 @@ fn main() -> std::io::Result<()> {
     sub_bytes[96..112].copy_from_slice(&channel_secret_bytes_2);
     sub_bytes[112..128].copy_from_slice(&make_complement_16b(&channel_secret_bytes_2));
 
+    // Write the single time window
+    let mut timestamp_bytes = [0u8; 16];
+    timestamp_bytes[0..8].copy_from_slice(&c0_start.to_le_bytes());
+    timestamp_bytes[8..16].copy_from_slice(&c0_end.to_le_bytes());
+    sub_bytes[128..144].copy_from_slice(&timestamp_bytes);
+    sub_bytes[144..160].copy_from_slice(&make_complement_16b(&timestamp_bytes));
+
     // Write subscription to firmware
     let c0_sub_start = FLASH_OFFSET_SUBSCRIPTION_BASE as usize;
-    output_firmware[c0_sub_start..c0_sub_start + 128].copy_from_slice(&sub_bytes);
+    output_firmware[c0_sub_start..c0_sub_start + sub_bytes.len()].copy_from_slice(&sub_bytes);
 
     // Write to final firmware file
     let mut output = File::create(args.output)?;
@@ -0,0 +1,1014 @@
+#![no_std]
+
+use bincode::de::read::{Reader, SliceReader};
+use bincode::{decode_from_reader, error::DecodeError};
+use common::checksum::{crc16, crc32};
+use common::constants::*;
+use common::crypto::{
+    compute_response_mac, derive_session_cipher_key, derive_session_key, make_session_nonce,
+};
+use common::{
+    AttestationKey, ConnectRequest, EncryptedFrameBatch, IdentityKey, MessageToDecoder,
+    SessionCipherKey, SessionKey, SessionNonce, BINCODE_CONFIG,
+};
+use core::convert::Infallible;
+use embedded_hal::delay::DelayNs;
+use embedded_hal_nb::nb::{self, block};
+use embedded_hal_nb::serial;
+use rand::RngCore;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+pub const MAX_MESSAGE_SIZE: usize = 0x400; // 1024 bytes, enough for a full subscription list
+pub const BLOCK_SIZE: usize = 0x100; // 256 bytes
+pub const BYTE_TIMEOUT_MS: u32 = 500; // Between bytes once a message has started
+pub const ACK_TIMEOUT_MS: u32 = 2_000; // For the host to acknowledge a header or block
+pub const BAUD_CONFIRM_TIMEOUT_MS: u32 = 1_000; // For the host to confirm a new baud rate
+
+const TIMEOUT_ERROR: &str = "UART timeout";
+
+/// Repeats the given expression 5 times. Unrolled, so a glitch cannot skip the rest by
+/// corrupting a loop counter or branch.
+macro_rules! repeat_5 {
+    ($e:expr) => {
+        $e;
+        $e;
+        $e;
+        $e;
+        $e;
+    };
+}
+
+/// A free-running 32-bit counter, used to bound how long the driver waits on the host.
+pub trait Clock {
+    /// Returns the current tick count, which wraps on overflow.
+    fn ticks(&self) -> u32;
+
+    /// Returns the number of ticks per millisecond.
+    fn ticks_per_ms(&self) -> u32;
+}
+
+/// A serial interface whose baud rate can be changed while it is in use.
+pub trait BaudRate {
+    /// Switches to the given baud rate, discarding any bytes already received.
+    fn set_baud_rate(&mut self, baud_rate: u32);
+}
+
+/// The Ascon-128 implementation used for encrypted sessions, so the firmware can provide its
+/// protected implementation while host builds use a portable one.
+pub trait SessionCipher {
+    /// Encrypts the message, writing the ciphertext and tag and returning their length.
+    fn encrypt(
+        &mut self,
+        message: &[u8],
+        nonce: &[u8; LEN_ASCON_NONCE],
+        key: &[u8; LEN_ASCON_KEY],
+        ciphertext: &mut [u8],
+    ) -> usize;
+
+    /// Decrypts and verifies the ciphertext, returning the length of the message, or None if
+    /// the ciphertext is invalid.
+    fn decrypt(
+        &mut self,
+        ciphertext: &[u8],
+        nonce: &[u8; LEN_ASCON_NONCE],
+        key: &[u8; LEN_ASCON_KEY],
+        message: &mut [u8],
+    ) -> Option<usize>;
+}
+
+/// The type of message being sent or received over the host transport interface.
+#[derive(Eq, PartialEq, Clone)]
+pub enum MessageType {
+    Invalid,
+    Decode,
+    Subscribe,
+    List,
+    Ack,
+    Error,
+    Debug,
+    Attest,
+    Connect,
+    Nack,
+    Baud,
+    DecodeBatch,
+    Telemetry,
+    FactoryReset,
+    CrashLog,
+}
+
+impl MessageType {
+    /// Returns the opcode byte used on the wire for this message type.
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            MessageType::Decode => b'D',
+            MessageType::Subscribe => b'S',
+            MessageType::List => b'L',
+            MessageType::Ack => b'A',
+            MessageType::Error => b'E',
+            MessageType::Debug => b'G',
+            MessageType::Attest => b'T',
+            MessageType::Connect => b'C',
+            MessageType::Nack => b'N',
+            MessageType::Baud => b'B',
+            MessageType::DecodeBatch => b'F',
+            MessageType::Telemetry => b'M',
+            MessageType::FactoryReset => b'R',
+            MessageType::CrashLog => b'P',
+            _ => b'E',
+        }
+    }
+}
+
+pub enum UartError {
+    Decode(DecodeError),
+    InvalidOpcode,
+    InvalidLength,
+    InvalidCiphertext,
+    InvalidChecksum,
+    Timeout,
+}
+
+impl From<DecodeError> for UartError {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::Other(TIMEOUT_ERROR) => UartError::Timeout,
+            e => UartError::Decode(e),
+        }
+    }
+}
+
+pub enum UartState {
+    None,
+    NumBytesRead(usize),
+}
+
+/// The state of a session negotiated by the host with a Connect message.
+pub struct Session {
+    key: SessionKey,
+    cipher_key: SessionCipherKey,
+    flags: u8,
+    tx_sequence: u32,
+    rx_sequence: u32,
+}
+
+pub struct MessageHeader {
+    pub opcode: MessageType,
+    pub length: u16,
+}
+
+impl MessageHeader {
+    pub fn new() -> Self {
+        Self {
+            opcode: MessageType::Invalid,
+            length: 0,
+        }
+    }
+
+    /// Returns true if the message should be acknowledged.
+    /// Should not ACK messages with opcode Ack, Nack or Debug.
+    pub fn should_ack(&self) -> bool {
+        !matches!(
+            self.opcode,
+            MessageType::Ack | MessageType::Nack | MessageType::Debug
+        )
+    }
+}
+
+impl Default for MessageHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Message {
+    pub header: MessageHeader,
+    pub data: [u8; MAX_MESSAGE_SIZE],
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Message {
+    pub fn new() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::Invalid,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn error() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::Error,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn ack() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::Ack,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn nack() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::Nack,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn list() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::List,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn subscribe() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::Subscribe,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    /// Creates a debug message, truncating the text to fit.
+    pub fn debug(message: &[u8]) -> Self {
+        let mut m = Self {
+            header: MessageHeader {
+                opcode: MessageType::Debug,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        };
+        m.add_data(message);
+        m
+    }
+
+    pub fn attest() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::Attest,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn connect() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::Connect,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn baud() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::Baud,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn decode() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::Decode,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn factory_reset() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::FactoryReset,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn telemetry() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::Telemetry,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn crash_log() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::CrashLog,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    pub fn decode_batch() -> Self {
+        Self {
+            header: MessageHeader {
+                opcode: MessageType::DecodeBatch,
+                length: 0,
+            },
+            data: [0u8; MAX_MESSAGE_SIZE],
+        }
+    }
+
+    /// Adds additional data to the message (increments the length). Data which does not fit
+    /// is dropped.
+    pub fn add_data(&mut self, data: &[u8]) {
+        let start = core::cmp::min(self.header.length as usize, MAX_MESSAGE_SIZE);
+        let end = core::cmp::min(start + data.len(), MAX_MESSAGE_SIZE);
+        self.data[start..end].copy_from_slice(&data[..end - start]);
+        self.header.length = end as u16;
+    }
+
+    /// Adds additional data to the message, bounded by the given length.
+    pub fn add_data_bounded(&mut self, data: &[u8], length: usize) {
+        self.add_data(&data[..core::cmp::min(length, data.len())]);
+    }
+}
+
+/// A driver for the host computer and decoder interface as described in the
+/// [eCTF 2025 Detailed Specifications](https://rules.ectf.mitre.org/2025/specs/detailed_specs.html).
+pub struct HostDriver<Serial, Rng, Delay, Clk, Cipher, SerialError = Infallible>
+where
+    Serial: serial::Read<u8, Error = SerialError> + serial::Write<u8, Error = SerialError>,
+    Rng: RngCore,
+    Delay: DelayNs,
+    Clk: Clock,
+    Cipher: SessionCipher,
+{
+    uart: Serial,
+    rng: Rng,
+    delay: Delay,
+    clock: Clk,
+    cipher: Cipher,
+    state: UartState,
+    session: Option<Session>,
+    checksums: bool,
+    baud_rate: u32,
+}
+
+impl<Serial, Rng, Delay, Clk, Cipher, SerialError> Reader
+    for HostDriver<Serial, Rng, Delay, Clk, Cipher, SerialError>
+where
+    Serial: serial::Read<u8, Error = SerialError> + serial::Write<u8, Error = SerialError>,
+    Rng: RngCore,
+    Delay: DelayNs,
+    Clk: Clock,
+    Cipher: SessionCipher,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), DecodeError> {
+        for b in buf.iter_mut() {
+            self.state = match self.state {
+                UartState::NumBytesRead(0) => {
+                    self.write_ack();
+                    UartState::NumBytesRead(1)
+                }
+                UartState::NumBytesRead(n @ 0..255) => UartState::NumBytesRead(n + 1),
+                UartState::NumBytesRead(_) => UartState::NumBytesRead(0),
+                // Bodies are only read after a header
+                UartState::None => return Err(DecodeError::Other("Invalid state")),
+            };
+            *b = self.read_byte().map_err(|e| match e {
+                UartError::Timeout => DecodeError::Other(TIMEOUT_ERROR),
+                _ => DecodeError::Other("UART read error"),
+            })?
+        }
+        Ok(())
+    }
+}
+
+impl<Serial, Rng, Delay, Clk, Cipher, SerialError>
+    HostDriver<Serial, Rng, Delay, Clk, Cipher, SerialError>
+where
+    Serial: serial::Read<u8, Error = SerialError> + serial::Write<u8, Error = SerialError>,
+    Rng: RngCore,
+    Delay: DelayNs,
+    Clk: Clock,
+    Cipher: SessionCipher,
+{
+    /// Create a new host transport driver with the given serial interface.
+    pub fn new(uart: Serial, rng: Rng, delay: Delay, clock: Clk, cipher: Cipher) -> Self {
+        Self {
+            uart,
+            rng,
+            delay,
+            clock,
+            cipher,
+            state: UartState::None,
+            session: None,
+            checksums: false,
+            baud_rate: DEFAULT_BAUD_RATE,
+        }
+    }
+
+    /// Free the serial interface and drop the host transport driver.
+    pub fn free(self) -> Serial {
+        self.uart
+    }
+
+    /// Returns true if the current session is encrypted. Only then are request bodies
+    /// authenticated and responses hidden from others on the UART.
+    pub fn is_encrypted(&self) -> bool {
+        self.session
+            .as_ref()
+            .is_some_and(|s| s.flags & CONNECT_FLAG_ENCRYPTION != 0)
+    }
+
+    /// Read a message from the host computer. Waits indefinitely for a message to start, but
+    /// gives up with a timeout if the host stops sending partway through.
+    pub fn read_message(&mut self) -> Result<MessageToDecoder, UartError> {
+        // Ask the host to retransmit corrupted headers
+        let header = loop {
+            match self.read_header(None) {
+                Err(UartError::InvalidChecksum) => self.reject(),
+                result => break result?,
+            }
+        };
+
+        self.state = UartState::NumBytesRead(0);
+
+        // Connect messages are never encrypted, as they start a new session
+        let encrypted = header.opcode != MessageType::Connect && self.is_encrypted();
+
+        // Bodies are buffered whole when they need to be checked or decrypted before parsing.
+        // ACK, NACK and Debug messages are not requests, and are rejected without reading them.
+        let result = match (header.should_ack(), encrypted || self.checksums) {
+            (false, _) => Err(UartError::InvalidOpcode),
+            (true, true) => self.read_buffered_body(&header, encrypted),
+            (true, false) => parse_message(&mut *self, &header.opcode, header.length as usize),
+        };
+
+        // A host which stopped sending is not waiting for an ACK, and neither is one which sent
+        // an ACK, NACK or Debug message
+        if header.should_ack() && !matches!(result, Err(UartError::Timeout)) {
+            self.write_ack();
+        }
+
+        // Random delay
+        repeat_5!(delay_random_us(&mut self.delay, &mut self.rng, 10, 3_000));
+
+        result
+    }
+
+    /// Read the whole body of a message, decrypting it in an encrypted session, then parse it.
+    fn read_buffered_body(
+        &mut self,
+        header: &MessageHeader,
+        encrypted: bool,
+    ) -> Result<MessageToDecoder, UartError> {
+        let length = header.length as usize;
+        let min_length = if encrypted { LEN_ASCON_TAG } else { 0 };
+        if !(min_length..=MAX_MESSAGE_SIZE).contains(&length) {
+            return Err(UartError::InvalidLength);
+        }
+
+        let mut ciphertext = [0u8; MAX_MESSAGE_SIZE];
+        self.read_body(&mut ciphertext[..length])?;
+
+        if !encrypted {
+            let result = parse_message(
+                &mut SliceReader::new(&ciphertext[..length]),
+                &header.opcode,
+                length,
+            );
+            ciphertext.zeroize();
+            return result;
+        }
+
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => return Err(UartError::InvalidCiphertext),
+        };
+        // The sequence advances even if decryption fails, as the host has already used it
+        let nonce = make_session_nonce(
+            SESSION_DIRECTION_TO_DECODER,
+            header.opcode.as_byte(),
+            session.rx_sequence,
+        );
+        session.rx_sequence = session.rx_sequence.wrapping_add(1);
+
+        let mut plaintext = [0u8; MAX_MESSAGE_SIZE];
+        let result = match self.cipher.decrypt(
+            &ciphertext[..length],
+            &nonce,
+            &session.cipher_key.0,
+            &mut plaintext[..length - LEN_ASCON_TAG],
+        ) {
+            Some(plaintext_length) => parse_message(
+                &mut SliceReader::new(&plaintext[..plaintext_length]),
+                &header.opcode,
+                plaintext_length,
+            ),
+            None => Err(UartError::InvalidCiphertext),
+        };
+        plaintext.zeroize();
+
+        result
+    }
+
+    /// Read the body of a message into the buffer. With checksums enabled, each block is
+    /// checked and the host is asked to retransmit it if corrupted.
+    fn read_body(&mut self, body: &mut [u8]) -> Result<(), UartError> {
+        if !self.checksums {
+            return self.read(body).map_err(UartError::from);
+        }
+
+        for start in (0..body.len()).step_by(BLOCK_SIZE) {
+            let end = core::cmp::min(start + BLOCK_SIZE, body.len());
+            // Acknowledges the header or the previous block
+            self.write_ack();
+
+            let mut attempts = 0;
+            while !self.read_block(&mut body[start..end])? {
+                if attempts == MAX_RETRANSMITS {
+                    return Err(UartError::InvalidChecksum);
+                }
+                attempts += 1;
+                self.reject();
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a data block and its checksum trailer, returning whether the checksum matches.
+    fn read_block(&mut self, block: &mut [u8]) -> Result<bool, UartError> {
+        for b in block.iter_mut() {
+            *b = self.read_byte()?;
+        }
+        let mut checksum = [0u8; LEN_BLOCK_CHECKSUM];
+        for b in checksum.iter_mut() {
+            *b = self.read_byte()?;
+        }
+        Ok(u32::from_le_bytes(checksum) == crc32(block))
+    }
+
+    /// Discard any bytes already received and ask the host to retransmit.
+    fn reject(&mut self) {
+        while self.uart.read().is_ok() {}
+        self.write_message(Message::nack());
+    }
+
+    /// Establish a new session with the host, enabling the requested features which are
+    /// supported. The session key is derived from the attestation key, the requested flags and
+    /// both parties' nonces, plus an X25519 exchange with the host's ephemeral key when
+    /// encryption is requested.
+    /// Any previous session is discarded. The response uses the framing in effect when the
+    /// request was sent, and any change to checksums applies to later messages.
+    pub fn connect(
+        &mut self,
+        request: &ConnectRequest,
+        attestation_key: &AttestationKey,
+        identity_key: &IdentityKey,
+    ) {
+        let mut flags = request.flags
+            & (CONNECT_FLAG_RESPONSE_AUTH | CONNECT_FLAG_ENCRYPTION | CONNECT_FLAG_CHECKSUM);
+        // Encrypted responses are already authenticated
+        if flags & CONNECT_FLAG_ENCRYPTION != 0 {
+            flags &= !CONNECT_FLAG_RESPONSE_AUTH;
+        }
+        let mut decoder_nonce = SessionNonce([0u8; LEN_SESSION_NONCE]);
+        self.rng.fill_bytes(&mut decoder_nonce.0);
+
+        // Shared secrets with both the decoder's ephemeral key and its identity key
+        let mut key_exchange = [0u8; 2 * LEN_X25519_KEY];
+        let mut key_exchange_length = 0;
+        let mut decoder_public_key = [0u8; LEN_X25519_KEY];
+        if flags & CONNECT_FLAG_ENCRYPTION != 0 {
+            let mut ephemeral_bytes = [0u8; LEN_X25519_KEY];
+            self.rng.fill_bytes(&mut ephemeral_bytes);
+            let ephemeral_secret = StaticSecret::from(ephemeral_bytes);
+            ephemeral_bytes.zeroize();
+            let identity_secret = StaticSecret::from(identity_key.0);
+            let host_public_key = PublicKey::from(request.host_public_key.0);
+
+            decoder_public_key = PublicKey::from(&ephemeral_secret).to_bytes();
+            key_exchange[..LEN_X25519_KEY]
+                .copy_from_slice(ephemeral_secret.diffie_hellman(&host_public_key).as_bytes());
+            key_exchange[LEN_X25519_KEY..]
+                .copy_from_slice(identity_secret.diffie_hellman(&host_public_key).as_bytes());
+            key_exchange_length = key_exchange.len();
+        }
+
+        self.session = match flags & (CONNECT_FLAG_RESPONSE_AUTH | CONNECT_FLAG_ENCRYPTION) {
+            0 => None,
+            _ => {
+                let key = derive_session_key(
+                    attestation_key,
+                    request.flags,
+                    &request.host_nonce,
+                    &decoder_nonce,
+                    &key_exchange[..key_exchange_length],
+                );
+                Some(Session {
+                    cipher_key: derive_session_cipher_key(&key),
+                    key,
+                    flags,
+                    tx_sequence: 0,
+                    rx_sequence: 0,
+                })
+            }
+        };
+        key_exchange.zeroize();
+
+        // The response is authenticated with the new session key, if any
+        let mut m = Message::connect();
+        m.add_data(&[flags]);
+        m.add_data(&decoder_nonce.0);
+        m.add_data(&decoder_public_key);
+        self.write_message(m);
+
+        self.checksums = flags & CONNECT_FLAG_CHECKSUM != 0;
+    }
+
+    /// Switch to a baud rate requested by the host. The decoder confirms the rate, then both
+    /// sides switch and the host sends an empty Baud message at the new rate, which the decoder
+    /// ACKs. If the rate is unsupported, the confirmation is not acknowledged, or the host's
+    /// message does not arrive within BAUD_CONFIRM_TIMEOUT_MS, the previous rate is kept.
+    pub fn change_baud_rate(&mut self, baud_rate: u32)
+    where
+        Serial: BaudRate,
+    {
+        if !SUPPORTED_BAUD_RATES.contains(&baud_rate) {
+            self.error();
+            return;
+        }
+
+        let mut m = Message::baud();
+        m.add_data(&baud_rate.to_le_bytes());
+        if self.send_message(m).is_err() {
+            return;
+        }
+
+        // The last ACK has arrived, but the UART may still be sending earlier bytes
+        let _ = block!(self.uart.flush());
+        self.uart.set_baud_rate(baud_rate);
+
+        match self.read_header(Some(BAUD_CONFIRM_TIMEOUT_MS)) {
+            Ok(header) if header.opcode == MessageType::Baud && header.length == 0 => {
+                self.write_ack();
+                self.baud_rate = baud_rate;
+            }
+            _ => self.uart.set_baud_rate(self.baud_rate),
+        }
+    }
+
+    /// Protect a message body according to the current session. Connect responses are always
+    /// authenticated, as the host needs them to confirm the session key.
+    fn protect_message(&mut self, message: &mut Message) {
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => return,
+        };
+        if let MessageType::Ack | MessageType::Nack = message.header.opcode {
+            return;
+        }
+
+        let opcode = message.header.opcode.as_byte();
+        let length = core::cmp::min(
+            message.header.length as usize,
+            MAX_MESSAGE_SIZE - LEN_ASCON_TAG,
+        );
+        if session.flags & CONNECT_FLAG_ENCRYPTION != 0
+            && message.header.opcode != MessageType::Connect
+        {
+            let nonce = make_session_nonce(SESSION_DIRECTION_TO_HOST, opcode, session.tx_sequence);
+            let mut ciphertext = [0u8; MAX_MESSAGE_SIZE];
+            let ciphertext_length = self.cipher.encrypt(
+                &message.data[..length],
+                &nonce,
+                &session.cipher_key.0,
+                &mut ciphertext,
+            );
+            message.data[..ciphertext_length].copy_from_slice(&ciphertext[..ciphertext_length]);
+            message.header.length = ciphertext_length as u16;
+        } else {
+            let mac = compute_response_mac(
+                &session.key,
+                session.tx_sequence,
+                opcode,
+                &message.data[..length],
+            );
+            message.header.length = length as u16;
+            message.add_data(&mac.0);
+        }
+        session.tx_sequence = session.tx_sequence.wrapping_add(1);
+    }
+
+    /// Write a message to the host computer.
+    pub fn write_message(&mut self, message: Message) {
+        let _ = self.send_message(message);
+    }
+
+    /// Write a message to the host computer, returning an error if the host did not acknowledge
+    /// all of it.
+    fn send_message(&mut self, mut message: Message) -> Result<(), UartError> {
+        self.protect_message(&mut message);
+
+        // Random delay
+        repeat_5!(delay_random_us(&mut self.delay, &mut self.rng, 10, 3_000));
+
+        // The header and each block are retransmitted if the host NACKs them, and the message is
+        // abandoned if the host stops responding
+        let mut attempts = 0;
+        loop {
+            let _ = self.write_header(&message.header);
+            if !message.header.should_ack() {
+                break;
+            }
+            match self.read_ack()? {
+                true => break,
+                false if attempts < MAX_RETRANSMITS => attempts += 1,
+                false => return Err(UartError::InvalidChecksum),
+            }
+        }
+        // Write data in blocks of BLOCK_SIZE (if necessary)
+        let mut bytes_written = 0;
+        let write_limit = core::cmp::min(message.header.length as usize, message.data.len());
+        while bytes_written < write_limit {
+            let end = core::cmp::min(bytes_written + BLOCK_SIZE, write_limit);
+            let mut attempts = 0;
+            loop {
+                self.write_block(&message.data[bytes_written..end]);
+                if !message.header.should_ack() {
+                    break;
+                }
+                match self.read_ack()? {
+                    true => break,
+                    false if attempts < MAX_RETRANSMITS => attempts += 1,
+                    false => return Err(UartError::InvalidChecksum),
+                }
+            }
+            bytes_written = end;
+        }
+        Ok(())
+    }
+
+    /// Helper function to write a data block, followed by its checksum if enabled.
+    fn write_block(&mut self, block: &[u8]) {
+        for b in block.iter() {
+            let _ = block!(self.uart.write(*b));
+        }
+        if self.checksums {
+            for b in crc32(block).to_le_bytes().iter() {
+                let _ = block!(self.uart.write(*b));
+            }
+        }
+    }
+
+    /// Read an ACK message from the host computer. Waits up to ACK_TIMEOUT_MS for an ACK or NACK,
+    /// discarding anything else, and returns whether it was an ACK.
+    pub fn read_ack(&mut self) -> Result<bool, UartError> {
+        let start = self.clock.ticks();
+        loop {
+            let elapsed_ms = self.clock.ticks().wrapping_sub(start) / self.clock.ticks_per_ms();
+            if elapsed_ms >= ACK_TIMEOUT_MS {
+                return Err(UartError::Timeout);
+            }
+            match self.read_header(Some(ACK_TIMEOUT_MS - elapsed_ms)) {
+                Ok(header) if header.opcode == MessageType::Ack => return Ok(true),
+                Ok(header) if header.opcode == MessageType::Nack => return Ok(false),
+                Err(UartError::Timeout) => return Err(UartError::Timeout),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Write an ACK message to the host computer.
+    pub fn write_ack(&mut self) {
+        self.write_message(Message::ack());
+    }
+
+    /// Write an error message to the host computer.
+    pub fn error(&mut self) {
+        self.write_message(Message::error());
+    }
+
+    /// Write a debug message to the host computer, truncated to fit in one message.
+    pub fn debug(&mut self, message: &[u8]) {
+        self.write_message(Message::debug(
+            &message[..core::cmp::min(message.len(), MAX_MESSAGE_SIZE - LEN_ASCON_TAG)],
+        ));
+    }
+
+    /// Helper function to read a header from the host computer, waiting up to `timeout_ms` for
+    /// it to start (or indefinitely if None).
+    fn read_header(&mut self, timeout_ms: Option<u32>) -> Result<MessageHeader, UartError> {
+        let mut header = MessageHeader::new();
+        let mut magic_value = 0u8;
+
+        // Magic
+        let start = self.clock.ticks();
+        while magic_value != b'%' {
+            magic_value = match timeout_ms {
+                Some(timeout_ms) => {
+                    let elapsed_ms =
+                        self.clock.ticks().wrapping_sub(start) / self.clock.ticks_per_ms();
+                    self.read_byte_within(timeout_ms.saturating_sub(elapsed_ms))?
+                }
+                None => block!(self.uart.read())
+                    .map_err(|_| UartError::Decode(DecodeError::Other("UART read error")))?,
+            };
+        }
+
+        // Opcode
+        let opcode = self.read_byte()?;
+
+        // Length
+        let mut length_bytes = [0u8; 2];
+        for b in length_bytes.iter_mut() {
+            *b = self.read_byte()?;
+        }
+        header.length = u16::from_le_bytes(length_bytes);
+
+        // Checksum, checked before trusting the opcode
+        if self.checksums {
+            let mut checksum = [0u8; LEN_HEADER_CHECKSUM];
+            for b in checksum.iter_mut() {
+                *b = self.read_byte()?;
+            }
+            if u16::from_le_bytes(checksum) != crc16(&[opcode, length_bytes[0], length_bytes[1]]) {
+                return Err(UartError::InvalidChecksum);
+            }
+        }
+
+        header.opcode = match opcode {
+            b'D' => MessageType::Decode,
+            b'S' => MessageType::Subscribe,
+            b'L' => MessageType::List,
+            b'A' => MessageType::Ack,
+            b'E' => MessageType::Error,
+            b'G' => MessageType::Debug,
+            b'T' => MessageType::Attest,
+            b'C' => MessageType::Connect,
+            b'N' => MessageType::Nack,
+            b'B' => MessageType::Baud,
+            b'F' => MessageType::DecodeBatch,
+            b'M' => MessageType::Telemetry,
+            b'R' => MessageType::FactoryReset,
+            b'P' => MessageType::CrashLog,
+            _ => return Err(UartError::InvalidOpcode),
+        };
+
+        Ok(header)
+    }
+
+    /// Helper function to read a single byte of a message from the host computer.
+    fn read_byte(&mut self) -> Result<u8, UartError> {
+        self.read_byte_within(BYTE_TIMEOUT_MS)
+    }
+
+    /// Helper function to read a single byte from the host computer, giving up after
+    /// `timeout_ms` without one.
+    fn read_byte_within(&mut self, timeout_ms: u32) -> Result<u8, UartError> {
+        let start = self.clock.ticks();
+        let timeout_ticks = timeout_ms.saturating_mul(self.clock.ticks_per_ms());
+        loop {
+            match self.uart.read() {
+                Ok(val) => return Ok(val),
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(_)) => {
+                    return Err(UartError::Decode(DecodeError::Other("UART read error")))
+                }
+            }
+            if self.clock.ticks().wrapping_sub(start) >= timeout_ticks {
+                return Err(UartError::Timeout);
+            }
+        }
+    }
+
+    /// Helper function to serialize a header for the host computer.
+    fn write_header(
+        &mut self,
+        header: &MessageHeader,
+    ) -> Result<(), embedded_hal_nb::nb::Error<SerialError>> {
+        let opcode = header.opcode.as_byte();
+
+        let length_bytes = header.length.to_le_bytes();
+
+        block!(self.uart.write(b'%'))?;
+        block!(self.uart.write(opcode))?;
+        for b in length_bytes.iter() {
+            block!(self.uart.write(*b))?;
+        }
+        if self.checksums {
+            let checksum = crc16(&[opcode, length_bytes[0], length_bytes[1]]);
+            for b in checksum.to_le_bytes().iter() {
+                block!(self.uart.write(*b))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the body of a message from the host, given its opcode and length.
+fn parse_message<R: Reader>(
+    reader: &mut R,
+    opcode: &MessageType,
+    length: usize,
+) -> Result<MessageToDecoder, UartError> {
+    match (opcode, length) {
+        (MessageType::List, 0) => Ok(MessageToDecoder::ListSubscriptions { flags: 0 }),
+        (MessageType::List, 1) => Ok(MessageToDecoder::ListSubscriptions {
+            flags: decode_from_reader(reader, BINCODE_CONFIG).map_err(UartError::from)?,
+        }),
+        (MessageType::Telemetry, 0) => Ok(MessageToDecoder::Telemetry { flags: 0 }),
+        (MessageType::Telemetry, 1) => Ok(MessageToDecoder::Telemetry {
+            flags: decode_from_reader(reader, BINCODE_CONFIG).map_err(UartError::from)?,
+        }),
+        (MessageType::CrashLog, 0) => Ok(MessageToDecoder::CrashLog),
+        (MessageType::Subscribe, LEN_ENCRYPTED_SUBSCRIPTION) => {
+            Ok(MessageToDecoder::UpdateSubscription(
+                decode_from_reader(reader, BINCODE_CONFIG).map_err(UartError::from)?,
+            ))
+        }
+        (MessageType::FactoryReset, LEN_ENCRYPTED_FACTORY_RESET) => {
+            Ok(MessageToDecoder::FactoryReset(
+                decode_from_reader(reader, BINCODE_CONFIG).map_err(UartError::from)?,
+            ))
+        }
+        (MessageType::Decode, LEN_ENCRYPTED_FRAME) => Ok(MessageToDecoder::DecodeFrame(
+            decode_from_reader(reader, BINCODE_CONFIG).map_err(UartError::from)?,
+        )),
+        (MessageType::DecodeBatch, length)
+            if length % LEN_ENCRYPTED_FRAME == 0
+                && (1..=MAX_BATCH_FRAMES).contains(&(length / LEN_ENCRYPTED_FRAME)) =>
+        {
+            let mut batch = EncryptedFrameBatch::new();
+            batch.num_frames = (length / LEN_ENCRYPTED_FRAME) as u8;
+            for frame in batch.frames[..length / LEN_ENCRYPTED_FRAME].iter_mut() {
+                *frame =
+                    decode_from_reader(&mut *reader, BINCODE_CONFIG).map_err(UartError::from)?;
+            }
+            Ok(MessageToDecoder::DecodeBatch(batch))
+        }
+        (MessageType::Attest, LEN_ATTESTATION_NONCE) => Ok(MessageToDecoder::Attest(
+            decode_from_reader(reader, BINCODE_CONFIG).map_err(UartError::from)?,
+        )),
+        (MessageType::Connect, LEN_CONNECT_REQUEST) => Ok(MessageToDecoder::Connect(
+            decode_from_reader(reader, BINCODE_CONFIG).map_err(UartError::from)?,
+        )),
+        (MessageType::Baud, LEN_BAUD_RATE) => Ok(MessageToDecoder::SetBaudRate(
+            decode_from_reader(reader, BINCODE_CONFIG).map_err(UartError::from)?,
+        )),
+        (
+            MessageType::List
+            | MessageType::Subscribe
+            | MessageType::FactoryReset
+            | MessageType::Decode
+            | MessageType::DecodeBatch
+            | MessageType::Attest
+            | MessageType::Connect
+            | MessageType::Baud
+            | MessageType::Telemetry
+            | MessageType::CrashLog,
+            _,
+        ) => Err(UartError::InvalidLength),
+        _ => Err(UartError::InvalidOpcode),
+    }
+}
+
+/// Delay for a random amount of time between `start_us` and `end_us`.
+fn delay_random_us<D, R>(delay: &mut D, rng: &mut R, start_us: u32, end_us: u32)
+where
+    D: DelayNs,
+    R: RngCore,
+{
+    let random_diff = rng.next_u32() % (end_us - start_us);
+    delay.delay_us(start_us + random_diff);
+}
@@ -0,0 +1,274 @@
+mod mock;
+
+use common::checksum::{crc16, crc32};
+use common::constants::*;
+use common::MessageToDecoder;
+use host_driver::{Message, UartError, BLOCK_SIZE};
+use mock::{ack, driver, header, Step};
+
+#[test]
+fn list_request_is_acked() {
+    let (mut host, script) = driver(vec![Step::Send(header(b'L', 0)), Step::Expect(ack())]);
+
+    let message = host.read_message();
+
+    assert!(matches!(
+        message,
+        Ok(MessageToDecoder::ListSubscriptions { flags: 0 })
+    ));
+    script.borrow().assert_finished();
+}
+
+#[test]
+fn response_is_acked_per_block() {
+    let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+    let mut steps = vec![Step::Expect(header(b'L', 600)), Step::Send(ack())];
+    for block in data.chunks(BLOCK_SIZE) {
+        steps.push(Step::Expect(block.to_vec()));
+        steps.push(Step::Send(ack()));
+    }
+    let (mut host, script) = driver(steps);
+
+    let mut m = Message::list();
+    m.add_data(&data);
+    host.write_message(m);
+
+    script.borrow().assert_finished();
+}
+
+#[test]
+fn invalid_opcodes_are_rejected_without_ack() {
+    for opcode in [b'Z', b'%', 0x00, 0xFF] {
+        let (mut host, script) = driver(vec![Step::Send(header(opcode, 0))]);
+
+        let message = host.read_message();
+
+        assert!(matches!(message, Err(UartError::InvalidOpcode)));
+        script.borrow().assert_finished();
+    }
+}
+
+#[test]
+fn wrong_lengths_are_rejected() {
+    let cases = [
+        (b'L', 2),
+        (b'S', LEN_ENCRYPTED_SUBSCRIPTION as u16 - 1),
+        (b'S', LEN_ENCRYPTED_SUBSCRIPTION as u16 + 1),
+        (b'D', 0),
+        (b'D', LEN_ENCRYPTED_FRAME as u16 + 1),
+        (b'T', LEN_ATTESTATION_NONCE as u16 - 1),
+        (b'C', 0),
+        (b'B', LEN_BAUD_RATE as u16 + 1),
+        (b'M', 2),
+        (b'P', 1),
+        (b'R', LEN_ENCRYPTED_FACTORY_RESET as u16 - 1),
+        (b'F', 0),
+        (b'F', LEN_ENCRYPTED_FRAME as u16 + 1),
+        (
+            b'F',
+            (MAX_BATCH_FRAMES + 1) as u16 * LEN_ENCRYPTED_FRAME as u16,
+        ),
+    ];
+    for (opcode, length) in cases {
+        let (mut host, script) = driver(vec![
+            Step::Send(header(opcode, length)),
+            Step::Expect(ack()),
+        ]);
+
+        let message = host.read_message();
+
+        assert!(matches!(message, Err(UartError::InvalidLength)));
+        script.borrow().assert_finished();
+    }
+}
+
+#[test]
+fn debug_messages_are_not_acked() {
+    let mut expected = header(b'G', 5);
+    expected.extend_from_slice(b"hello");
+    let (mut host, script) = driver(vec![Step::Expect(expected)]);
+
+    host.write_message(Message::debug(b"hello"));
+
+    script.borrow().assert_finished();
+    assert_eq!(script.borrow().reads(), 0, "Decoder waited for an ACK");
+}
+
+#[test]
+fn debug_messages_from_host_are_not_acked() {
+    let (mut host, script) = driver(vec![Step::Send(header(b'G', 3))]);
+
+    let result = host.read_message();
+
+    assert!(matches!(result, Err(UartError::InvalidOpcode)));
+    script.borrow().assert_finished();
+}
+
+#[test]
+fn acks_from_host_are_not_acked() {
+    let (mut host, script) = driver(vec![Step::Send(ack())]);
+
+    let result = host.read_message();
+
+    assert!(matches!(result, Err(UartError::InvalidOpcode)));
+    script.borrow().assert_finished();
+}
+
+#[test]
+fn host_stopping_mid_message_times_out() {
+    let (mut host, script) = driver(vec![
+        Step::Send(header(b'S', LEN_ENCRYPTED_SUBSCRIPTION as u16)),
+        Step::Expect(ack()),
+        Step::Send(vec![0u8; 10]),
+    ]);
+
+    let result = host.read_message();
+
+    assert!(matches!(result, Err(UartError::Timeout)));
+    script.borrow().assert_finished();
+}
+
+#[test]
+fn response_is_abandoned_without_ack() {
+    let (mut host, script) = driver(vec![Step::Expect(header(b'L', 4))]);
+
+    let mut m = Message::list();
+    m.add_data(&[0u8; 4]);
+    host.write_message(m);
+
+    script.borrow().assert_finished();
+}
+
+#[test]
+fn baud_rate_request_is_parsed() {
+    let (mut host, script) = driver(vec![
+        Step::Send(header(b'B', LEN_BAUD_RATE as u16)),
+        Step::Expect(ack()),
+        Step::Send(921_600u32.to_le_bytes().to_vec()),
+        Step::Expect(ack()),
+    ]);
+
+    let message = host.read_message();
+
+    assert!(matches!(
+        message,
+        Ok(MessageToDecoder::SetBaudRate(921_600))
+    ));
+    script.borrow().assert_finished();
+}
+
+#[test]
+fn baud_rate_is_switched_after_confirmation() {
+    let (mut host, script) = driver(vec![
+        Step::Expect(header(b'B', LEN_BAUD_RATE as u16)),
+        Step::Send(ack()),
+        Step::Expect(921_600u32.to_le_bytes().to_vec()),
+        Step::Send(ack()),
+        Step::Send(header(b'B', 0)),
+        Step::Expect(ack()),
+    ]);
+
+    host.change_baud_rate(921_600);
+
+    script.borrow().assert_finished();
+    assert_eq!(script.borrow().baud_rates(), [921_600]);
+}
+
+#[test]
+fn baud_rate_falls_back_without_confirmation() {
+    let (mut host, script) = driver(vec![
+        Step::Expect(header(b'B', LEN_BAUD_RATE as u16)),
+        Step::Send(ack()),
+        Step::Expect(921_600u32.to_le_bytes().to_vec()),
+        Step::Send(ack()),
+    ]);
+
+    host.change_baud_rate(921_600);
+
+    script.borrow().assert_finished();
+    assert_eq!(script.borrow().baud_rates(), [921_600, DEFAULT_BAUD_RATE]);
+}
+
+#[test]
+fn unsupported_baud_rate_is_refused() {
+    let (mut host, script) = driver(vec![Step::Expect(header(b'E', 0)), Step::Send(ack())]);
+
+    host.change_baud_rate(57_600);
+
+    script.borrow().assert_finished();
+    assert!(script.borrow().baud_rates().is_empty());
+}
+
+#[test]
+fn batch_decode_request_is_acked_per_block() {
+    // A body within one block, and bodies spanning several
+    for num_frames in [1, 3, MAX_BATCH_FRAMES] {
+        let body: Vec<u8> = (0..num_frames * LEN_ENCRYPTED_FRAME)
+            .map(|i| i as u8)
+            .collect();
+        let mut steps = vec![
+            Step::Send(header(b'F', body.len() as u16)),
+            Step::Expect(ack()),
+        ];
+        // The host waits for an ACK after every full block and after the final partial one
+        for block in body.chunks(BLOCK_SIZE) {
+            steps.push(Step::Send(block.to_vec()));
+            steps.push(Step::Expect(ack()));
+        }
+        let (mut host, script) = driver(steps);
+
+        let message = host.read_message();
+
+        match message {
+            Ok(MessageToDecoder::DecodeBatch(batch)) => {
+                assert_eq!(batch.frames().len(), num_frames);
+                for (frame, expected) in batch.frames().iter().zip(body.chunks(LEN_ENCRYPTED_FRAME))
+                {
+                    assert_eq!(frame.0[..], expected[..]);
+                }
+            }
+            _ => panic!("Expected a batch decode"),
+        }
+        script.borrow().assert_finished();
+    }
+}
+
+#[test]
+fn telemetry_request_is_parsed() {
+    let (mut host, script) = driver(vec![
+        Step::Send(header(b'M', 1)),
+        Step::Expect(ack()),
+        Step::Send(vec![TELEMETRY_FLAG_RESET]),
+        Step::Expect(ack()),
+    ]);
+
+    let message = host.read_message();
+
+    assert!(matches!(
+        message,
+        Ok(MessageToDecoder::Telemetry {
+            flags: TELEMETRY_FLAG_RESET
+        })
+    ));
+    script.borrow().assert_finished();
+}
+
+#[test]
+fn crash_log_request_is_parsed() {
+    let (mut host, script) = driver(vec![Step::Send(header(b'P', 0)), Step::Expect(ack())]);
+
+    let message = host.read_message();
+
+    assert!(matches!(message, Ok(MessageToDecoder::CrashLog)));
+    script.borrow().assert_finished();
+}
+
+#[test]
+fn checksums_match_the_host_tools() {
+    // The check values of CRC-16/CCITT-FALSE and CRC-32, which the host tools compute with
+    // binascii.crc_hqx(data, 0xFFFF) and binascii.crc32(data)
+    assert_eq!(crc16(b"123456789"), 0x29B1);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc16(b""), 0xFFFF);
+    assert_eq!(crc32(b""), 0);
+}
@@ -0,0 +1,244 @@
+// Each test binary uses a different part of the mock
+#![allow(dead_code)]
+
+use core::convert::Infallible;
+use embedded_hal::delay::DelayNs;
+use embedded_hal_nb::{nb, serial};
+use host_driver::{BaudRate, Clock, HostDriver, SessionCipher};
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// One step of the conversation between the host and the decoder.
+pub enum Step {
+    /// The host sends these bytes to the decoder.
+    Send(Vec<u8>),
+    /// The decoder must write exactly these bytes before the host continues.
+    Expect(Vec<u8>),
+}
+
+/// The shared state of a scripted conversation.
+#[derive(Default)]
+pub struct Script {
+    steps: VecDeque<Step>,
+    reads: usize,
+    baud_rates: Vec<u32>,
+}
+
+impl Script {
+    /// Returns the number of times the decoder polled for a byte.
+    pub fn reads(&self) -> usize {
+        self.reads
+    }
+
+    /// Returns every baud rate the decoder switched to, in order.
+    pub fn baud_rates(&self) -> &[u32] {
+        &self.baud_rates
+    }
+
+    /// Asserts that every step of the script has been played.
+    pub fn assert_finished(&self) {
+        match self.steps.front() {
+            None => {}
+            Some(Step::Send(bytes)) => panic!("Decoder did not read {bytes:?}"),
+            Some(Step::Expect(bytes)) => panic!("Decoder did not write {bytes:?}"),
+        }
+    }
+}
+
+/// A serial port which plays the host's side of a script. Host bytes only become readable
+/// once the decoder has written everything expected before them, so the order of ACKs and
+/// blocks is checked as well as their contents.
+pub struct MockSerial {
+    script: Rc<RefCell<Script>>,
+}
+
+impl serial::ErrorType for MockSerial {
+    type Error = Infallible;
+}
+
+impl serial::Read<u8> for MockSerial {
+    fn read(&mut self) -> nb::Result<u8, Infallible> {
+        let mut script = self.script.borrow_mut();
+        script.reads += 1;
+        let byte = match script.steps.front_mut() {
+            Some(Step::Send(bytes)) => bytes.remove(0),
+            _ => return Err(nb::Error::WouldBlock),
+        };
+        if let Some(Step::Send(bytes)) = script.steps.front() {
+            if bytes.is_empty() {
+                script.steps.pop_front();
+            }
+        }
+        Ok(byte)
+    }
+}
+
+impl serial::Write<u8> for MockSerial {
+    fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+        let mut script = self.script.borrow_mut();
+        let expected = match script.steps.front_mut() {
+            Some(Step::Expect(bytes)) => bytes.remove(0),
+            Some(Step::Send(bytes)) => panic!("Decoder wrote {byte:#04x} before reading {bytes:?}"),
+            None => panic!("Decoder wrote {byte:#04x} after the script ended"),
+        };
+        assert_eq!(byte, expected, "Decoder wrote an unexpected byte");
+        if let Some(Step::Expect(bytes)) = script.steps.front() {
+            if bytes.is_empty() {
+                script.steps.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl BaudRate for MockSerial {
+    fn set_baud_rate(&mut self, baud_rate: u32) {
+        self.script.borrow_mut().baud_rates.push(baud_rate);
+    }
+}
+
+/// A delay which returns immediately.
+pub struct NoDelay;
+
+impl DelayNs for NoDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
+}
+
+/// A clock which advances by one millisecond every time it is read, so timeouts expire
+/// after a bounded number of polls.
+#[derive(Default)]
+pub struct MockClock {
+    ticks: Cell<u32>,
+}
+
+impl Clock for MockClock {
+    fn ticks(&self) -> u32 {
+        let ticks = self.ticks.get();
+        self.ticks.set(ticks.wrapping_add(1));
+        ticks
+    }
+
+    fn ticks_per_ms(&self) -> u32 {
+        1
+    }
+}
+
+/// A cipher with no secrecy, for every test driver. The ciphertext is the message
+/// followed by a tag of zeros, and any other tag is rejected.
+pub struct PlainCipher;
+
+impl SessionCipher for PlainCipher {
+    fn encrypt(
+        &mut self,
+        message: &[u8],
+        _: &[u8; 16],
+        _: &[u8; 16],
+        ciphertext: &mut [u8],
+    ) -> usize {
+        ciphertext[..message.len()].copy_from_slice(message);
+        ciphertext[message.len()..message.len() + 16].fill(0);
+        message.len() + 16
+    }
+
+    fn decrypt(
+        &mut self,
+        ciphertext: &[u8],
+        _: &[u8; 16],
+        _: &[u8; 16],
+        message: &mut [u8],
+    ) -> Option<usize> {
+        let (body, tag) = ciphertext.split_at(ciphertext.len() - 16);
+        if tag != [0u8; 16] {
+            return None;
+        }
+        message[..body.len()].copy_from_slice(body);
+        Some(body.len())
+    }
+}
+
+/// A serial port which feeds the decoder whatever bytes are queued, then nothing, and accepts
+/// anything the decoder writes.
+pub struct FeedSerial {
+    input: Rc<RefCell<VecDeque<u8>>>,
+}
+
+impl serial::ErrorType for FeedSerial {
+    type Error = Infallible;
+}
+
+impl serial::Read<u8> for FeedSerial {
+    fn read(&mut self) -> nb::Result<u8, Infallible> {
+        self.input
+            .borrow_mut()
+            .pop_front()
+            .ok_or(nb::Error::WouldBlock)
+    }
+}
+
+impl serial::Write<u8> for FeedSerial {
+    fn write(&mut self, _byte: u8) -> nb::Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+pub type MockDriver = HostDriver<MockSerial, ChaCha20Rng, NoDelay, MockClock, PlainCipher>;
+pub type FeedDriver = HostDriver<FeedSerial, ChaCha20Rng, NoDelay, MockClock, PlainCipher>;
+
+/// Create a driver which plays the given script, and a handle to check its progress.
+pub fn driver(steps: Vec<Step>) -> (MockDriver, Rc<RefCell<Script>>) {
+    let script = Rc::new(RefCell::new(Script {
+        steps: steps.into(),
+        ..Default::default()
+    }));
+    let serial = MockSerial {
+        script: script.clone(),
+    };
+    let driver = HostDriver::new(
+        serial,
+        ChaCha20Rng::seed_from_u64(0),
+        NoDelay,
+        MockClock::default(),
+        PlainCipher,
+    );
+    (driver, script)
+}
+
+/// Create a driver which reads whatever bytes are queued in the returned handle, and ignores
+/// what it writes.
+pub fn feed_driver() -> (FeedDriver, Rc<RefCell<VecDeque<u8>>>) {
+    let input = Rc::new(RefCell::new(VecDeque::new()));
+    let serial = FeedSerial {
+        input: input.clone(),
+    };
+    let driver = HostDriver::new(
+        serial,
+        ChaCha20Rng::seed_from_u64(0),
+        NoDelay,
+        MockClock::default(),
+        PlainCipher,
+    );
+    (driver, input)
+}
+
+/// Build a message header without checksums.
+pub fn header(opcode: u8, length: u16) -> Vec<u8> {
+    let mut header = vec![b'%', opcode];
+    header.extend_from_slice(&length.to_le_bytes());
+    header
+}
+
+/// Build an ACK message without checksums.
+pub fn ack() -> Vec<u8> {
+    header(b'A', 0)
+}
@@ -0,0 +1,176 @@
+mod mock;
+
+use common::checksum::{crc16, crc32};
+use common::constants::*;
+use common::{AttestationKey, ConnectRequest, IdentityKey, SessionNonce, X25519PublicKey};
+use host_driver::{Message, BLOCK_SIZE, MAX_MESSAGE_SIZE};
+use mock::{feed_driver, header, FeedDriver};
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+/// Body lengths on either side of every length the decoder accepts, plus extremes.
+fn lengths() -> Vec<usize> {
+    let mut lengths = vec![
+        0,
+        1,
+        2,
+        0xFF,
+        0x100,
+        0x101,
+        MAX_MESSAGE_SIZE,
+        MAX_MESSAGE_SIZE + 1,
+    ];
+    for length in [
+        LEN_BAUD_RATE,
+        LEN_ASCON_TAG,
+        LEN_ATTESTATION_NONCE,
+        LEN_CONNECT_REQUEST,
+        LEN_ENCRYPTED_FRAME,
+        MAX_BATCH_FRAMES * LEN_ENCRYPTED_FRAME,
+        LEN_ENCRYPTED_SUBSCRIPTION,
+        LEN_ENCRYPTED_FACTORY_RESET,
+    ] {
+        lengths.extend([length - 1, length, length + 1]);
+    }
+    lengths
+}
+
+/// Start a session with the given Connect flags.
+fn connect(host: &mut FeedDriver, flags: u8) {
+    let request = ConnectRequest {
+        flags,
+        host_nonce: SessionNonce([0x11; LEN_SESSION_NONCE]),
+        host_public_key: X25519PublicKey([0x22; LEN_X25519_KEY]),
+    };
+    host.connect(
+        &request,
+        &AttestationKey([0x33; LEN_ATTESTATION_KEY]),
+        &IdentityKey([0x44; LEN_IDENTITY_KEY]),
+    );
+}
+
+/// Feed the driver one message for every opcode and interesting length, framing a random body
+/// with `frame`, and read each one. Anything left unread is discarded before the next message.
+/// A panic fails the test; the results do not matter.
+fn read_every_message(setup: impl FnOnce(&mut FeedDriver), frame: impl Fn(u8, &[u8]) -> Vec<u8>) {
+    let (mut host, input) = feed_driver();
+    setup(&mut host);
+    let mut rng = ChaCha20Rng::seed_from_u64(0);
+    for opcode in 0..=u8::MAX {
+        for length in lengths() {
+            let mut body = vec![0u8; length];
+            rng.fill_bytes(&mut body);
+            input.borrow_mut().clear();
+            input.borrow_mut().extend(frame(opcode, &body));
+
+            let _ = host.read_message();
+        }
+    }
+}
+
+#[test]
+fn no_message_panics_without_session() {
+    read_every_message(
+        |_| {},
+        |opcode, body| {
+            let mut bytes = header(opcode, body.len() as u16);
+            bytes.extend_from_slice(body);
+            bytes
+        },
+    );
+}
+
+#[test]
+fn no_message_panics_with_checksums() {
+    read_every_message(
+        |host| connect(host, CONNECT_FLAG_CHECKSUM),
+        |opcode, body| {
+            let length = (body.len() as u16).to_le_bytes();
+            let mut bytes = header(opcode, body.len() as u16);
+            bytes.extend_from_slice(&crc16(&[opcode, length[0], length[1]]).to_le_bytes());
+            for block in body.chunks(BLOCK_SIZE) {
+                bytes.extend_from_slice(block);
+                bytes.extend_from_slice(&crc32(block).to_le_bytes());
+            }
+            bytes
+        },
+    );
+}
+
+#[test]
+fn no_message_panics_with_bad_block_checksums() {
+    // A bad header checksum makes the decoder wait for the header to be resent, so only the
+    // blocks are corrupted
+    read_every_message(
+        |host| connect(host, CONNECT_FLAG_CHECKSUM),
+        |opcode, body| {
+            let length = (body.len() as u16).to_le_bytes();
+            let mut bytes = header(opcode, body.len() as u16);
+            bytes.extend_from_slice(&crc16(&[opcode, length[0], length[1]]).to_le_bytes());
+            for block in body.chunks(BLOCK_SIZE) {
+                bytes.extend_from_slice(block);
+                bytes.extend_from_slice(&[0u8; 4]);
+            }
+            bytes
+        },
+    );
+}
+
+#[test]
+fn no_message_panics_in_encrypted_session() {
+    read_every_message(
+        |host| connect(host, CONNECT_FLAG_ENCRYPTION),
+        |opcode, body| {
+            // The test cipher accepts any body followed by a tag of zeros
+            let mut bytes = header(opcode, (body.len() + LEN_ASCON_TAG) as u16);
+            bytes.extend_from_slice(body);
+            bytes.extend_from_slice(&[0u8; LEN_ASCON_TAG]);
+            bytes
+        },
+    );
+}
+
+#[test]
+fn no_message_panics_with_bad_tags() {
+    read_every_message(
+        |host| connect(host, CONNECT_FLAG_ENCRYPTION),
+        |opcode, body| {
+            let mut bytes = header(opcode, body.len() as u16);
+            bytes.extend_from_slice(body);
+            bytes
+        },
+    );
+}
+
+#[test]
+fn oversized_message_data_is_truncated() {
+    let mut m = Message::list();
+    m.add_data(&[0xAA; MAX_MESSAGE_SIZE - 1]);
+    m.add_data(&[0xBB; 4]);
+    m.add_data_bounded(&[0xCC; 4], 8);
+    assert_eq!(m.header.length as usize, MAX_MESSAGE_SIZE);
+    assert_eq!(m.data[MAX_MESSAGE_SIZE - 1], 0xBB);
+
+    let m = Message::debug(&[b'x'; 2 * MAX_MESSAGE_SIZE]);
+    assert_eq!(m.header.length as usize, MAX_MESSAGE_SIZE);
+
+    let mut m = Message::decode();
+    m.add_data_bounded(&[0xDD; 4], 8);
+    assert_eq!(m.header.length, 4);
+}
+
+#[test]
+fn only_encrypted_sessions_are_encrypted() {
+    for (flags, encrypted) in [
+        (0, false),
+        (CONNECT_FLAG_RESPONSE_AUTH, false),
+        (CONNECT_FLAG_CHECKSUM, false),
+        (CONNECT_FLAG_ENCRYPTION, true),
+        (CONNECT_FLAG_RESPONSE_AUTH | CONNECT_FLAG_ENCRYPTION, true),
+    ] {
+        let (mut host, _) = feed_driver();
+        assert!(!host.is_encrypted());
+        connect(&mut host, flags);
+        assert_eq!(host.is_encrypted(), encrypted, "flags {flags:#04x}");
+    }
+}
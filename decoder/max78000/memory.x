@@ -3,20 +3,13 @@ MEMORY {
    BOOTLOADER  (rx) : ORIGIN = 0x10000000, LENGTH = 0x0000E000 /* Bootloader flash */
    FLASH       (rx) : ORIGIN = 0x1000E000, LENGTH = 0x00032000 /* Location of team firmware */
    SECRETS     (rw) : ORIGIN = 0x10040000, LENGTH = 0x00016000 /* Reserved */
-   RESERVED    (rw) : ORIGIN = 0x10056000, LENGTH = 0x00028000 /* Reserved */
+   STATE       (rw) : ORIGIN = 0x10056000, LENGTH = 0x00008000 /* Failure, tamper, crash and reset counter pages (see README.md) */
+   RESERVED    (rw) : ORIGIN = 0x1005E000, LENGTH = 0x00020000 /* Reserved */
    ROM_BL_PAGE (rw) : ORIGIN = 0x1007E000, LENGTH = 0x00002000 /* Reserved */
-   RAM         (rwx): ORIGIN = 0x20000000, LENGTH = 0x00010000 /* 64kB RAM */
+   RAM         (rw) : ORIGIN = 0x20000000, LENGTH = 0x00010000 /* 64kB RAM, never executed (see src/mpu.rs) */
 }
 
 _stext = ORIGIN(FLASH) + 0x200; /* Jump point for bootloader */
 
-SECTIONS {
-   .flash_code :
-   {
-      . = ALIGN(4);
-      *(.flashprog*)
-      . = ALIGN(4);
-   } > RAM AT>FLASH
-}
-
-INSERT AFTER .data;
\ No newline at end of file
+/* No code is copied to RAM, as the MPU makes it non-executable. The HAL's flashprog-linkage
+   feature must stay disabled, so flash routines run from flash. */
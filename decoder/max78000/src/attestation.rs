@@ -0,0 +1,50 @@
+use crate::crypto::{get_attestation_key, get_decoder_id, get_firmware_measurement};
+use crate::hardening::{eq_twice, HardenedBool};
+use common::constants::{FLASH_ADDR_BASE, FLASH_FIRMWARE_CODE_SIZE};
+use common::crypto::{compute_attestation_mac, measure_firmware};
+use common::{AttestationNonce, AttestationResponse, FirmwareMeasurement};
+use core::cell::Cell;
+use critical_section::Mutex;
+use zeroize::Zeroize;
+
+static FIRMWARE_INTACT: Mutex<Cell<HardenedBool>> = Mutex::new(Cell::new(HardenedBool::FALSE));
+
+/// Measures the firmware code region in flash.
+pub fn measure_code_region() -> FirmwareMeasurement {
+    let code = unsafe {
+        core::slice::from_raw_parts(
+            FLASH_ADDR_BASE as *const u8,
+            FLASH_FIRMWARE_CODE_SIZE as usize,
+        )
+    };
+    measure_firmware(code)
+}
+
+/// Measure the code region and compare it with the measurement the firmware builder stored
+/// alongside the keys. No key can be read until this finds a match.
+pub fn init_measurement() {
+    let measurement = measure_code_region();
+    let expected = get_firmware_measurement();
+    let intact = eq_twice(&measurement.0, &expected.0);
+    critical_section::with(|cs| FIRMWARE_INTACT.borrow(cs).set(intact));
+}
+
+/// Returns whether the code region matched its stored measurement at boot.
+pub fn firmware_intact() -> HardenedBool {
+    critical_section::with(|cs| FIRMWARE_INTACT.borrow(cs).get())
+}
+
+/// Answers an attestation challenge from the host with a MAC over the nonce, decoder ID, and
+/// firmware measurement.
+pub fn attest(nonce: &AttestationNonce) -> AttestationResponse {
+    let decoder_id = get_decoder_id();
+    let measurement = measure_code_region();
+    let mut attestation_key = get_attestation_key();
+    let mac = compute_attestation_mac(&attestation_key, nonce, decoder_id, &measurement);
+    attestation_key.zeroize();
+    AttestationResponse {
+        decoder_id,
+        measurement,
+        mac,
+    }
+}
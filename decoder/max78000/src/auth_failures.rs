@@ -0,0 +1,81 @@
+use crate::flash_log::FlashLog;
+use common::constants::*;
+use core::cell::Cell;
+use critical_section::Mutex;
+use hal::flc::Flc;
+
+// The failure page is a flash log with one record per failure, so the count can only go down by
+// erasing the page after an authenticated factory reset.
+
+const LOG: FlashLog = FlashLog {
+    addr: FLASH_ADDR_AUTH_FAILURES,
+    magic: FLASH_MAGIC_AUTH_FAILURES,
+    first_record: 16,
+    record_len: 16,
+    max_records: MAX_AUTH_FAILURES,
+};
+const RECORD_FAILURE: [u8; 16] = [0u8; 16];
+
+static AUTH_FAILURES: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+static CYCLES_PER_MS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Load the persistent failure count, initializing the failure page on first boot, then apply
+/// any back-off owed so a reset does not skip it. `sys_clk_frequency` is used to time the
+/// back-off.
+pub fn init_auth_failures(flc: &mut Flc, sys_clk_frequency: u32) {
+    critical_section::with(|cs| CYCLES_PER_MS.borrow(cs).set(sys_clk_frequency / 1_000));
+
+    let count = LOG.init(flc);
+    critical_section::with(|cs| AUTH_FAILURES.borrow(cs).set(count));
+    back_off(count);
+}
+
+/// Returns true if so many authentication failures have been recorded that the decoder refuses
+/// to decrypt anything.
+pub fn auth_locked_out() -> bool {
+    let count = critical_section::with(|cs| AUTH_FAILURES.borrow(cs).get());
+    match AUTH_FAILURE_LOCKOUT {
+        Some(lockout) => core::hint::black_box(count) >= core::hint::black_box(lockout),
+        None => false,
+    }
+}
+
+/// Persistently record a failed authentication, then wait out the back-off for the new count.
+pub fn record_auth_failure(flc: &mut Flc) {
+    let count = critical_section::with(|cs| AUTH_FAILURES.borrow(cs).get());
+    // A failed write still counts, and nothing is written once the page is full
+    let _ = LOG.append(flc, count, &RECORD_FAILURE);
+    let count = core::cmp::min(count + 1, MAX_AUTH_FAILURES);
+    critical_section::with(|cs| AUTH_FAILURES.borrow(cs).set(count));
+    back_off(count);
+}
+
+/// Forget every recorded failure, unless the decoder is locked out. Only called after an
+/// authenticated factory reset: anyone can replay captured frames, so successful decodes never
+/// clear the count. The page is only erased when it holds failures.
+pub fn clear_auth_failures(flc: &mut Flc) {
+    if auth_locked_out() {
+        return;
+    }
+    let count = critical_section::with(|cs| AUTH_FAILURES.borrow(cs).get());
+    if count > 0 && LOG.reset(flc).is_err() {
+        return;
+    }
+    critical_section::with(|cs| AUTH_FAILURES.borrow(cs).set(0));
+}
+
+/// Busy-wait for the back-off owed after `count` failures.
+fn back_off(count: u32) {
+    if count <= AUTH_FAILURE_GRACE {
+        return;
+    }
+    let doublings = core::cmp::min(count - AUTH_FAILURE_GRACE - 1, 31);
+    let delay_ms = core::cmp::min(
+        (AUTH_FAILURE_BASE_DELAY_MS as u64) << doublings,
+        AUTH_FAILURE_MAX_DELAY_MS as u64,
+    );
+    let cycles_per_ms = critical_section::with(|cs| CYCLES_PER_MS.borrow(cs).get());
+    for _ in 0..delay_ms {
+        cortex_m::asm::delay(cycles_per_ms);
+    }
+}
@@ -0,0 +1,111 @@
+#![cfg_attr(not(feature = "crash-log"), allow(dead_code))]
+
+use crate::flash_log::FlashLog;
+use crate::hardening::wipe_and_reset;
+use common::constants::*;
+use core::cell::{Cell, RefCell};
+use core::panic::{Location, PanicInfo};
+use core::sync::atomic::{AtomicBool, Ordering};
+use critical_section::Mutex;
+use hal::flc::Flc;
+use hal::gcr::clocks::{Clock, SystemClock};
+use hal::pac;
+
+// The crash log page is a flash log with one record per panic until it is full. Only the panic
+// location is recorded, never any data: the line (4B), column (4B) and the end of the source
+// path (24B).
+
+const LOG: FlashLog = FlashLog {
+    addr: FLASH_ADDR_CRASH_LOG,
+    magic: FLASH_MAGIC_CRASH_LOG,
+    first_record: 16,
+    record_len: LEN_CRASH_RECORD as u32,
+    max_records: MAX_CRASH_RECORDS,
+};
+
+// Flash is written in 16-byte blocks
+const _: () = assert!(LEN_CRASH_RECORD % 16 == 0);
+
+static PANICKED: AtomicBool = AtomicBool::new(false);
+static CRASH_LOG_FLC: Mutex<RefCell<Option<Flc>>> = Mutex::new(RefCell::new(None));
+static CRASH_RECORDS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Wipes every key and decrypted picture from RAM and resets, after recording the location of
+/// the panic in the crash log if enabled. A panic cannot leave secrets behind while the core
+/// spins, so an induced panic gains an attacker nothing.
+#[panic_handler]
+#[cfg_attr(not(feature = "crash-log"), allow(unused_variables))]
+fn panic(info: &PanicInfo) -> ! {
+    // A panic while recording a panic goes straight to the reset
+    if !PANICKED.swap(true, Ordering::Relaxed) {
+        #[cfg(feature = "crash-log")]
+        if let Some(location) = info.location() {
+            record_crash(location);
+        }
+    }
+    wipe_and_reset()
+}
+
+/// A panic location read back from the crash log.
+pub struct CrashRecord {
+    pub line: u32,
+    pub column: u32,
+    pub file: [u8; LEN_CRASH_FILE],
+}
+
+/// Find the crash log, initializing its page on first boot. Keeps a flash controller of its
+/// own, so a panic can be recorded from anywhere.
+pub fn init_crash_log(sys_clk: Clock<SystemClock>) {
+    // Safety: This flash controller is only used here and when recording a panic, neither of
+    // which happens during another flash operation
+    let mut flc = Flc::new(unsafe { pac::Flc::steal() }, sys_clk);
+
+    let count = LOG.init(&mut flc);
+    critical_section::with(|cs| {
+        CRASH_RECORDS.borrow(cs).set(count);
+        CRASH_LOG_FLC.borrow(cs).replace(Some(flc));
+    });
+}
+
+/// Append the given panic location to the crash log, keeping the end of the source path.
+fn record_crash(location: &Location) {
+    let file = location.file().as_bytes();
+    let file = &file[file.len().saturating_sub(LEN_CRASH_FILE)..];
+    let mut record = [0u8; LEN_CRASH_RECORD];
+    record[0..4].copy_from_slice(&location.line().to_le_bytes());
+    record[4..8].copy_from_slice(&location.column().to_le_bytes());
+    record[8..8 + file.len()].copy_from_slice(file);
+
+    critical_section::with(|cs| {
+        let count = CRASH_RECORDS.borrow(cs).get();
+        if count >= MAX_CRASH_RECORDS {
+            return;
+        }
+        if let Some(flc) = CRASH_LOG_FLC.borrow(cs).borrow_mut().as_mut() {
+            let _ = LOG.append(flc, count, &record);
+        }
+        CRASH_RECORDS.borrow(cs).set(count + 1);
+    });
+}
+
+/// Returns the total number of panics recorded.
+pub fn crash_count() -> u32 {
+    critical_section::with(|cs| CRASH_RECORDS.borrow(cs).get())
+}
+
+/// Calls `f` with each of the most recent `CRASH_LOG_REPORTED_RECORDS` panics, oldest first.
+pub fn read_crash_log(flc: &mut Flc, mut f: impl FnMut(&CrashRecord)) {
+    let count = crash_count();
+    let first = count.saturating_sub(CRASH_LOG_REPORTED_RECORDS as u32);
+    for idx in first..count {
+        let mut bytes = [0u8; LEN_CRASH_RECORD];
+        // Whatever cannot be read is reported as zeros
+        let _ = LOG.read(flc, idx, &mut bytes);
+        let record = CrashRecord {
+            line: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            column: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            file: bytes[8..].try_into().unwrap(),
+        };
+        f(&record);
+    }
+}
@@ -1,37 +1,143 @@
-use ascon_sys::crypto_aead_decrypt;
+use crate::attestation::firmware_intact;
+use crate::hardening::fault;
+use crate::mpu::with_secrets;
+use crate::tamper::TamperEvent;
+use ascon_sys::{crypto_aead_decrypt, crypto_aead_encrypt};
 use common::constants::{
-    FLASH_ADDR_FRAME_KEY, FLASH_ADDR_SUBSCRIPTION_KEY, LEN_ASCON_AEAD_OVERHEAD, LEN_ASCON_KEY,
-    LEN_ASCON_NONCE, LEN_ASCON_TAG,
+    FLASH_ADDR_ATTESTATION_KEY, FLASH_ADDR_DECODER_ID, FLASH_ADDR_FIRMWARE_MEASUREMENT,
+    FLASH_ADDR_FRAME_KEY, FLASH_ADDR_IDENTITY_KEY, FLASH_ADDR_SUBSCRIPTION_KEY,
+    LEN_ASCON_AEAD_OVERHEAD, LEN_ASCON_KEY, LEN_ASCON_NONCE, LEN_ASCON_TAG, LEN_ATTESTATION_KEY,
+    LEN_FIRMWARE_MEASUREMENT, LEN_IDENTITY_KEY,
 };
-use common::{FrameKey, SubscriptionKey};
+use common::{AttestationKey, FirmwareMeasurement, FrameKey, IdentityKey, SubscriptionKey};
+use host_driver::SessionCipher;
 
 /// The error types that can be encountered during decryption
 pub enum DecryptError {
     InvalidCiphertext,
+    BufferTooSmall,
+}
+
+/// The protected Ascon implementation, for encrypted host sessions.
+pub struct AsconCipher;
+
+impl SessionCipher for AsconCipher {
+    fn encrypt(
+        &mut self,
+        message: &[u8],
+        nonce: &[u8; LEN_ASCON_NONCE],
+        key: &[u8; LEN_ASCON_KEY],
+        ciphertext: &mut [u8],
+    ) -> usize {
+        internal_encrypt_ascon(message, nonce, key, ciphertext)
+    }
+
+    fn decrypt(
+        &mut self,
+        ciphertext: &[u8],
+        nonce: &[u8; LEN_ASCON_NONCE],
+        key: &[u8; LEN_ASCON_KEY],
+        message: &mut [u8],
+    ) -> Option<usize> {
+        internal_decrypt_ascon(ciphertext, nonce, key, message).ok()
+    }
+}
+
+/// Run `f` with the secrets page readable, if the code region matched its measurement at boot.
+/// Commands which need a key are refused when it did not, so getting here anyway means that
+/// check was skipped.
+fn with_keys<T>(f: impl FnOnce() -> T) -> T {
+    firmware_intact().assert_true();
+    with_secrets(f)
 }
 
 /// Get the frame key from flash memory.
 pub fn get_frame_key() -> FrameKey {
-    let frame_key_bytes =
-        unsafe { core::ptr::read_volatile(FLASH_ADDR_FRAME_KEY as *const [u8; LEN_ASCON_KEY]) };
+    let frame_key_bytes = with_keys(|| unsafe {
+        core::ptr::read_volatile(FLASH_ADDR_FRAME_KEY as *const [u8; LEN_ASCON_KEY])
+    });
     FrameKey(frame_key_bytes)
 }
 
 /// Get the subscription key from flash memory.
 pub fn get_subscription_key() -> SubscriptionKey {
-    let subscription_key_bytes = unsafe {
+    let subscription_key_bytes = with_keys(|| unsafe {
         core::ptr::read_volatile(FLASH_ADDR_SUBSCRIPTION_KEY as *const [u8; LEN_ASCON_KEY])
-    };
+    });
     SubscriptionKey(subscription_key_bytes)
 }
 
+/// Get the attestation key from flash memory.
+pub fn get_attestation_key() -> AttestationKey {
+    let attestation_key_bytes = with_keys(|| unsafe {
+        core::ptr::read_volatile(FLASH_ADDR_ATTESTATION_KEY as *const [u8; LEN_ATTESTATION_KEY])
+    });
+    AttestationKey(attestation_key_bytes)
+}
+
+/// Get the decoder identity key from flash memory.
+pub fn get_identity_key() -> IdentityKey {
+    let identity_key_bytes = with_keys(|| unsafe {
+        core::ptr::read_volatile(FLASH_ADDR_IDENTITY_KEY as *const [u8; LEN_IDENTITY_KEY])
+    });
+    IdentityKey(identity_key_bytes)
+}
+
+/// Get the decoder ID from flash memory.
+pub fn get_decoder_id() -> u32 {
+    with_secrets(|| unsafe { core::ptr::read_volatile(FLASH_ADDR_DECODER_ID as *const u32) })
+}
+
+/// Get the measurement of the code region taken by the firmware builder from flash memory.
+pub fn get_firmware_measurement() -> FirmwareMeasurement {
+    let measurement_bytes = with_secrets(|| unsafe {
+        core::ptr::read_volatile(
+            FLASH_ADDR_FIRMWARE_MEASUREMENT as *const [u8; LEN_FIRMWARE_MEASUREMENT],
+        )
+    });
+    FirmwareMeasurement(measurement_bytes)
+}
+
+/// Encrypt the message with the given nonce and Ascon key, returning the length of the
+/// ciphertext (including the tag) written to the output.
+pub fn internal_encrypt_ascon(
+    message: &[u8],
+    nonce: &[u8; LEN_ASCON_NONCE],
+    key: &[u8; LEN_ASCON_KEY],
+    ciphertext: &mut [u8],
+) -> usize {
+    assert!(ciphertext.len() >= message.len() + LEN_ASCON_TAG);
+
+    let mut clen: u64 = 0;
+    unsafe {
+        crypto_aead_encrypt(
+            ciphertext.as_mut_ptr(),
+            &mut clen,
+            message.as_ptr(),
+            message.len() as u64,
+            core::ptr::null_mut(),
+            0,
+            core::ptr::null_mut(),
+            nonce.as_ptr(),
+            key.as_ptr(),
+        )
+    };
+
+    clen as usize
+}
+
 pub fn internal_decrypt_ascon(
     ciphertext: &[u8],
     nonce: &[u8; LEN_ASCON_NONCE],
     key: &[u8; LEN_ASCON_KEY],
     message: &mut [u8],
 ) -> Result<usize, DecryptError> {
-    assert!(message.len() + LEN_ASCON_TAG >= ciphertext.len());
+    if ciphertext.len() < LEN_ASCON_TAG {
+        return Err(DecryptError::InvalidCiphertext);
+    }
+    if message.len() + LEN_ASCON_TAG < ciphertext.len() {
+        return Err(DecryptError::BufferTooSmall);
+    }
 
     let mut mlen: u64 = 0;
     let result = unsafe {
@@ -51,7 +157,8 @@ pub fn internal_decrypt_ascon(
     match result {
         -1 => Err(DecryptError::InvalidCiphertext),
         0 => Ok(mlen as usize),
-        _ => unreachable!("Ascon returned unexpected length"),
+        // Ascon only ever returns 0 or -1
+        _ => fault(TamperEvent::Unreachable),
     }
 }
 
@@ -61,9 +168,13 @@ pub fn decrypt_ascon(
     key: &[u8; LEN_ASCON_KEY],
     output_bytes: &mut [u8],
 ) -> Result<usize, DecryptError> {
-    assert!(ascon_data.len() >= LEN_ASCON_AEAD_OVERHEAD);
-    let nonce = &ascon_data[..LEN_ASCON_NONCE];
-    let ciphertext = &ascon_data[LEN_ASCON_NONCE..];
+    if ascon_data.len() < LEN_ASCON_AEAD_OVERHEAD {
+        return Err(DecryptError::InvalidCiphertext);
+    }
+    let (nonce, ciphertext) = ascon_data.split_at(LEN_ASCON_NONCE);
+    let nonce = nonce
+        .try_into()
+        .map_err(|_| DecryptError::InvalidCiphertext)?;
 
-    internal_decrypt_ascon(ciphertext, nonce.try_into().unwrap(), key, output_bytes)
+    internal_decrypt_ascon(ciphertext, nonce, key, output_bytes)
 }
@@ -1,5 +1,8 @@
+use crate::auth_failures::record_auth_failure;
 use crate::crypto::{decrypt_ascon, get_frame_key};
+use crate::hardening::{check_twice, random_delay, FlowCounter, HardenedBool};
 use crate::subscription::get_channel_subscription;
+use crate::telemetry::{record_frame_decoded, record_frame_rejected, FrameRejection};
 use bincode::decode_from_slice;
 use common::constants::*;
 use common::crypto::derive_picture_key;
@@ -7,51 +10,129 @@ use common::{DecryptedFrame, EncryptedFrame, Picture, SizedPicture, Timestamp, B
 use hal::flc::Flc;
 use zeroize::Zeroize;
 
+/// The number of steps on the success path of `decode_frame`, verified before a picture is
+/// returned.
+const DECODE_STEPS: u32 = 5;
+
 /// Decrypts the outer frame and returns a DecryptedFrame.
 /// No metadata validation is performed.
 pub fn decrypt_frame(enc_frame: &EncryptedFrame) -> Result<DecryptedFrame, ()> {
     let mut dec_frame_bytes = [0u8; LEN_DECRYPTED_FRAME];
     let mut frame_key = get_frame_key();
-    match decrypt_ascon(&enc_frame.0, &frame_key.0, &mut dec_frame_bytes) {
-        Ok(LEN_DECRYPTED_FRAME) => {}
-        _ => return Err(()),
-    };
+    let decrypted = HardenedBool::new(matches!(
+        decrypt_ascon(&enc_frame.0, &frame_key.0, &mut dec_frame_bytes),
+        Ok(LEN_DECRYPTED_FRAME)
+    ));
     frame_key.zeroize();
+    if decrypted.is_false() {
+        return Err(());
+    }
     let dec_frame: DecryptedFrame = match decode_from_slice(&dec_frame_bytes, BINCODE_CONFIG) {
         Ok((frame, LEN_DECRYPTED_FRAME)) => frame,
         _ => return Err(()),
     };
+    decrypted.assert_true();
     Ok(dec_frame)
 }
 
+/// How a decoded frame counts towards the persistent authentication failure count.
+#[derive(Clone, Copy)]
+pub enum AuthOutcome {
+    /// A tag did not verify.
+    Failed,
+    /// Every tag verified, even if the frame was rejected for its metadata, or the frame was
+    /// never decoded.
+    Neither,
+}
+
+impl AuthOutcome {
+    /// Returns how the result of `decode_frame` counts.
+    pub fn of(result: &Result<SizedPicture, FrameRejection>) -> Self {
+        match result {
+            Err(FrameRejection::InvalidFrame | FrameRejection::InvalidPicture) => {
+                AuthOutcome::Failed
+            }
+            _ => AuthOutcome::Neither,
+        }
+    }
+
+    /// Record the outcome. This writes flash and may back off, so it must only be called once
+    /// the padded decode has been answered, or the response time would depend on the outcome.
+    /// Only tag failures cost anything, so the host can still tell them from other rejections
+    /// by when the next command is handled.
+    pub fn record(self, flc: &mut Flc) {
+        match self {
+            AuthOutcome::Failed => record_auth_failure(flc),
+            AuthOutcome::Neither => {}
+        }
+    }
+}
+
+/// Fully decodes a frame from the host, updating the timestamp if it is valid.
+/// The outcome is counted in the telemetry, but not as an authentication failure,
+/// which the caller records with `AuthOutcome` after responding.
+pub fn decode_frame(
+    flc: &mut Flc,
+    timestamp: &mut Timestamp,
+    enc_frame: &EncryptedFrame,
+) -> Result<SizedPicture, FrameRejection> {
+    let mut flow = FlowCounter::new();
+    let dec_frame = match decrypt_frame(enc_frame) {
+        Ok(dec_frame) => dec_frame,
+        Err(_) => {
+            record_frame_rejected(&FrameRejection::InvalidFrame);
+            return Err(FrameRejection::InvalidFrame);
+        }
+    };
+    flow.step();
+    random_delay();
+    match validate_and_decrypt_picture(flc, timestamp, &dec_frame, &mut flow) {
+        Ok(pic) => {
+            flow.verify(DECODE_STEPS);
+            record_frame_decoded(dec_frame.channel_id);
+            Ok(pic)
+        }
+        Err(reason) => {
+            record_frame_rejected(&reason);
+            Err(reason)
+        }
+    }
+}
+
 /// Validates the metadata of the decrypted frame and decrypts the picture.
+/// Each check is made twice, and each passed check is counted in `flow`.
 pub fn validate_and_decrypt_picture(
     flc: &mut Flc,
     timestamp: &mut Timestamp,
     dec_frame: &DecryptedFrame,
-) -> Result<SizedPicture, ()> {
-    assert!(
-        dec_frame.picture_length as usize <= MAX_LEN_PICTURE,
-        "Invalid picture length"
-    );
+    flow: &mut FlowCounter,
+) -> Result<SizedPicture, FrameRejection> {
+    if dec_frame.picture_length as usize > MAX_LEN_PICTURE {
+        return Err(FrameRejection::InvalidFrame);
+    }
     // Get the subscription for the channel
     let mut subscription = match get_channel_subscription(flc, dec_frame.channel_id) {
         Ok(sub) => sub,
-        Err(_) => return Err(()),
+        Err(_) => return Err(FrameRejection::NoSubscription),
     };
-    for _ in 0..core::hint::black_box(3) {
-        // Ensure the timestamp is within the subscription range
-        if core::hint::black_box(dec_frame.timestamp) < core::hint::black_box(subscription.info.start)
-            || core::hint::black_box(dec_frame.timestamp) > core::hint::black_box(subscription.info.end)
-        {
-            return Err(());
-        }
-        // Ensure the timestamp is greater than the last seen timestamp
-        if core::hint::black_box(dec_frame.timestamp) <= core::hint::black_box(timestamp.0) {
-            return Err(());
-        }
+    flow.step();
+    // Ensure the timestamp is within one of the subscription windows
+    let windows = subscription.info.windows();
+    let in_window = check_twice(|| windows.iter().any(|w| w.contains(dec_frame.timestamp)));
+    if in_window.is_false() {
+        return Err(FrameRejection::OutsideWindow);
+    }
+    flow.step();
+    // Ensure the timestamp is greater than the last seen timestamp
+    let is_fresh = check_twice(|| dec_frame.timestamp > timestamp.0);
+    if is_fresh.is_false() {
+        return Err(FrameRejection::StaleTimestamp);
     }
-    // At this point, we have validated all the metadata
+    flow.step();
+    // At this point, we have validated all the metadata. Check again in case a glitch skipped
+    // one of the branches above.
+    in_window.assert_true();
+    is_fresh.assert_true();
     // Update the timestamp
     timestamp.0 = dec_frame.timestamp;
     // Derive the picture key
@@ -59,15 +140,20 @@ pub fn validate_and_decrypt_picture(
     subscription.zeroize();
     // Decrypt the picture
     let mut dec_picture_bytes = [0u8; MAX_LEN_PICTURE];
-    match decrypt_ascon(
-        &dec_frame.encrypted_picture.0,
-        &picture_key.0,
-        &mut dec_picture_bytes,
-    ) {
-        Ok(MAX_LEN_PICTURE) => {}
-        _ => return Err(()),
-    };
+    let decrypted = HardenedBool::new(matches!(
+        decrypt_ascon(
+            &dec_frame.encrypted_picture.0,
+            &picture_key.0,
+            &mut dec_picture_bytes,
+        ),
+        Ok(MAX_LEN_PICTURE)
+    ));
     picture_key.zeroize();
+    if decrypted.is_false() {
+        return Err(FrameRejection::InvalidPicture);
+    }
+    flow.step();
+    decrypted.assert_true();
     // Initialize the plaintext picture
     let res = SizedPicture {
         picture_length: dec_frame.picture_length,
@@ -0,0 +1,19 @@
+use cortex_m::delay::Delay;
+use embedded_hal::delay::DelayNs;
+
+/// Adapts the SysTick delay to the `embedded_hal` delay trait used by the host driver.
+pub struct SysTickDelay(pub Delay);
+
+impl DelayNs for SysTickDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.0.delay_us(ns.div_ceil(1_000));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.0.delay_us(us);
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.0.delay_ms(ms);
+    }
+}
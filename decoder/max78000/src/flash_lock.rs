@@ -0,0 +1,73 @@
+use crate::hardening::{check_twice, fault};
+use crate::tamper::TamperEvent;
+use common::constants::*;
+use hal::flc::{Flc, FLASH_BASE};
+use hal::pac;
+
+// The firmware image (code, random bytes, keys and the emergency subscription) never changes once
+// provisioned, so its pages are write-locked through the flash controller. The lock only lasts
+// until the next external or power-on reset, so it is applied on every boot. The standard
+// subscription pages and the pages recording authentication failures, faults, panics and factory
+// resets after the image stay writable.
+// ┌───────────────────────────┐
+// │Code (25 pages)            │ Locked
+// │Random Bytes               │ Locked
+// │Keys                       │ Locked
+// │Emergency Subscription     │ Locked
+// ├───────────────────────────┤
+// │Subscriptions (8 pages)    │ Writable
+// │Authentication Failures    │ Writable
+// │Tamper Events              │ Writable
+// │Crash Log                  │ Writable
+// │Reset Counter              │ Writable
+// └───────────────────────────┘
+
+const FIRST_IMAGE_PAGE: u32 = (FLASH_ADDR_BASE - FLASH_BASE) / FLASH_PAGE_SIZE;
+const IMAGE_PAGES: u32 = FLASH_FIRMWARE_SIZE / FLASH_PAGE_SIZE;
+const FIRST_WRITABLE_PAGE: u32 = FIRST_IMAGE_PAGE + IMAGE_PAGES;
+const WRITABLE_PAGES: u32 =
+    (FLASH_ADDR_RESET_COUNTER - FLASH_ADDR_SUBSCRIPTION_BASE) / FLASH_PAGE_SIZE;
+
+// The image ends with the emergency subscription, and every page the firmware writes follows it,
+// ending with the reset counter
+const _: () = assert!(FLASH_FIRMWARE_SIZE == FLASH_OFFSET_SUBSCRIPTION_BASE + FLASH_PAGE_SIZE);
+const _: () = assert!(
+    FLASH_ADDR_AUTH_FAILURES < FLASH_ADDR_RESET_COUNTER
+        && FLASH_ADDR_TAMPER_EVENTS < FLASH_ADDR_RESET_COUNTER
+        && FLASH_ADDR_CRASH_LOG < FLASH_ADDR_RESET_COUNTER
+);
+
+/// One bit per flash page, set for each page of the firmware image.
+const IMAGE_MASK: u64 = ((1 << IMAGE_PAGES) - 1) << FIRST_IMAGE_PAGE;
+/// One bit per flash page, set for each page the firmware writes.
+const WRITABLE_MASK: u64 = ((1 << WRITABLE_PAGES) - 1) << FIRST_WRITABLE_PAGE;
+
+/// Write-lock every page of the firmware image until the next reset.
+pub fn lock_flash(flc: &Flc) {
+    for page in 0..IMAGE_PAGES {
+        flc.disable_page_write(FLASH_ADDR_BASE + page * FLASH_PAGE_SIZE)
+            .expect("Failed to lock firmware page");
+    }
+}
+
+/// Returns one bit per flash page, set for each page which can be written or erased.
+fn writable_pages() -> u64 {
+    // Safety: Reading the write lock registers has no side effects
+    let flc = unsafe { &*pac::Flc::ptr() };
+    let low = flc.welr0().read().bits() as u64;
+    let high = flc.welr1().read().bits() as u64;
+    (high << 32) | low
+}
+
+/// Faults unless every page of the firmware image is write-locked and every page the firmware
+/// writes is not.
+pub fn check_flash_lock() {
+    let locked = check_twice(|| {
+        let writable = writable_pages();
+        writable & IMAGE_MASK == 0 && writable & WRITABLE_MASK == WRITABLE_MASK
+    });
+    if locked.is_false() {
+        fault(TamperEvent::FlashUnlocked);
+    }
+    locked.assert_true();
+}
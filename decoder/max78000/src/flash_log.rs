@@ -0,0 +1,108 @@
+use crate::subscription::{read_16b, write_16b};
+use hal::flc::{FlashError, Flc};
+
+// A flash page used as an append-only log. It starts with a header, optionally followed by data
+// stored along with it, then holds fixed-size records. Records are only ever written, so a record
+// can only be removed by erasing the whole page, and a record which was partly written when
+// power was lost still counts. The first erased record marks the end of the log.
+// ┌───────────────────────────┐
+// │Magic (8B), ~Magic (8B)    │
+// │...                        │
+// │Record 1                   │
+// │...                        │
+// │Record N                   │
+// │Erased                     │
+// │...                        │
+// └───────────────────────────┘
+
+/// An append-only log of records in one flash page.
+pub struct FlashLog {
+    /// The address of the page.
+    pub addr: u32,
+    /// The magic byte in the header which marks the page as initialized.
+    pub magic: u8,
+    /// The offset of the first record, after the header and anything stored with it.
+    pub first_record: u32,
+    /// The length of each record, a multiple of 16 bytes.
+    pub record_len: u32,
+    /// The number of records which fit in the page.
+    pub max_records: u32,
+}
+
+impl FlashLog {
+    /// Returns the header which marks an initialized page.
+    fn header(&self) -> [u8; 16] {
+        let mut header = [self.magic; 16];
+        for b in header[8..].iter_mut() {
+            *b = !self.magic;
+        }
+        header
+    }
+
+    /// Returns the address of the record at the given index.
+    fn record_addr(&self, idx: u32) -> u32 {
+        self.addr + self.first_record + idx * self.record_len
+    }
+
+    /// Returns the number of records in the log, or None if the page has not been initialized.
+    /// A record counts unless its first 16 bytes are erased.
+    pub fn count(&self, flc: &mut Flc) -> Option<u32> {
+        let mut block = [0u8; 16];
+        if read_16b(flc, self.addr, &mut block).is_err() || block != self.header() {
+            return None;
+        }
+        for idx in 0..self.max_records {
+            if read_16b(flc, self.record_addr(idx), &mut block).is_ok() && block == [0xFF; 16] {
+                return Some(idx);
+            }
+        }
+        Some(self.max_records)
+    }
+
+    /// Erase the page and write its header, leaving no records. If power is lost before the
+    /// header is written, the page is reinitialized at the next boot.
+    pub fn reset(&self, flc: &mut Flc) -> Result<(), FlashError> {
+        unsafe {
+            flc.erase_page(self.addr)?;
+        }
+        write_16b(flc, self.addr, &self.header())
+    }
+
+    /// Returns the number of records in the log, first initializing the page if it holds
+    /// anything but a log. A page which was never initialized may hold anything, so it is
+    /// treated as empty.
+    pub fn init(&self, flc: &mut Flc) -> u32 {
+        match self.count(flc) {
+            Some(count) => count,
+            None => {
+                self.reset(flc).expect("Failed to initialize flash log");
+                0
+            }
+        }
+    }
+
+    /// Write the record at the given index, which must be the number of records in the log.
+    /// Nothing is written once the log is full. Even if the write fails, the record no longer
+    /// reads as erased, so it still counts.
+    pub fn append(&self, flc: &mut Flc, idx: u32, record: &[u8]) -> Result<(), FlashError> {
+        assert!(record.len() == self.record_len as usize);
+        if idx >= self.max_records {
+            return Err(FlashError::InvalidAddress);
+        }
+        let addr = self.record_addr(idx);
+        for (offset, block) in (0..).step_by(16).zip(record.chunks_exact(16)) {
+            write_16b(flc, addr + offset, block.try_into().unwrap())?;
+        }
+        Ok(())
+    }
+
+    /// Read the record at the given index into `record`.
+    pub fn read(&self, flc: &mut Flc, idx: u32, record: &mut [u8]) -> Result<(), FlashError> {
+        assert!(record.len() == self.record_len as usize);
+        let addr = self.record_addr(idx);
+        for (offset, block) in (0..).step_by(16).zip(record.chunks_exact_mut(16)) {
+            read_16b(flc, addr + offset, block.try_into().unwrap())?;
+        }
+        Ok(())
+    }
+}
@@ -1,25 +1,145 @@
-use cortex_m::delay::Delay;
-use rand::RngCore;
+use crate::mpu::disable_mpu;
+use crate::rng::random_u32;
+use crate::tamper::{record_tamper, TamperEvent};
+use core::hint::black_box;
+use cortex_m::peripheral::SCB;
+use host_driver::Clock;
 
-/// Delay for a random amount of time between `start_us` and `end_us`.
-pub fn delay_random_us<R>(delay: &mut Delay, rng: &mut R, start_us: u32, end_us: u32)
+/// Start and end of RAM, which must match `memory.x`.
+pub const RAM_START: u32 = 0x2000_0000;
+pub const RAM_END: u32 = 0x2001_0000;
+
+/// Upper bound for `random_delay`, in cycles (20 us at 100 MHz).
+const MAX_RANDOM_DELAY_CYCLES: u32 = 2_000;
+
+/// Busy-wait for a short random time drawn from the global RNG, so that a glitch aimed at
+/// the code which follows is hard to time.
+pub fn random_delay() {
+    cortex_m::asm::delay(random_u32() % MAX_RANDOM_DELAY_CYCLES);
+}
+
+/// Run `f`, then wait until at least `budget_us` have passed since it started (if set), so the
+/// time taken does not reveal which path `f` took. Returns immediately if `f` overran.
+pub fn pad_to_budget_us<C, T>(clock: &C, budget_us: Option<u32>, f: impl FnOnce() -> T) -> T
 where
-    R: RngCore,
+    C: Clock,
 {
-    assert!(start_us < end_us);
-    let random_diff = rng.next_u32() % (end_us - start_us);
-    let random_delay = start_us + random_diff;
-    delay.delay_us(random_delay);
-}
-
-/// Macro that repeats the given expression 5 times
-#[macro_export]
-macro_rules! repeat_5 {
-    ($e:expr) => {
-        $e;
-        $e;
-        $e;
-        $e;
-        $e;
-    };
+    let start = clock.ticks();
+    let result = f();
+    if let Some(budget_us) = budget_us {
+        let budget_ticks = (budget_us as u64 * clock.ticks_per_ms() as u64 / 1_000) as u32;
+        while clock.ticks().wrapping_sub(start) < budget_ticks {}
+    }
+    result
+}
+
+/// A boolean stored as one of two 32-bit patterns which differ in every bit, so that a glitch
+/// which flips bits or skips the instruction setting it cannot turn one value into the other.
+/// Any other pattern is treated as a fault.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct HardenedBool(u32);
+
+impl HardenedBool {
+    pub const TRUE: Self = Self(0x5AA5_5AA5);
+    pub const FALSE: Self = Self(0xA55A_A55A);
+
+    pub fn new(value: bool) -> Self {
+        if black_box(value) {
+            Self::TRUE
+        } else {
+            Self::FALSE
+        }
+    }
+
+    /// Returns true if the value is `TRUE`, and faults if it is neither `TRUE` nor `FALSE`.
+    pub fn is_true(self) -> bool {
+        match black_box(self) {
+            Self::TRUE => true,
+            Self::FALSE => false,
+            _ => fault(TamperEvent::InvalidState),
+        }
+    }
+
+    /// Returns true if the value is `FALSE`, and faults if it is neither `TRUE` nor `FALSE`.
+    pub fn is_false(self) -> bool {
+        !self.is_true()
+    }
+
+    /// Faults unless the value is `TRUE`. Use this after branching on a check, to catch a
+    /// glitch which skipped the branch.
+    pub fn assert_true(self) {
+        if black_box(self) != Self::TRUE {
+            fault(TamperEvent::SkippedCheck);
+        }
+    }
+}
+
+/// Evaluate `check` twice with a random delay in between, and fault if the results disagree.
+/// A single glitch can then only change the outcome by hitting both evaluations.
+pub fn check_twice(check: impl Fn() -> bool) -> HardenedBool {
+    let first = HardenedBool::new(check());
+    random_delay();
+    let second = HardenedBool::new(check());
+    if black_box(first) != black_box(second) {
+        fault(TamperEvent::CheckMismatch);
+    }
+    first
+}
+
+/// Compare two values twice with a random delay in between, and fault if the results disagree.
+pub fn eq_twice<T: PartialEq>(a: &T, b: &T) -> HardenedBool {
+    check_twice(|| black_box(a) == black_box(b))
+}
+
+/// Counts the steps taken through a command. Each step on the success path calls `step`, and
+/// the total is verified before the result is used, so a glitch which skips a step (or jumps
+/// past a check) is caught.
+pub struct FlowCounter(u32);
+
+impl FlowCounter {
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    #[inline(always)]
+    pub fn step(&mut self) {
+        self.0 = black_box(self.0) + 1;
+    }
+
+    /// Faults unless exactly `expected` steps were taken.
+    pub fn verify(&self, expected: u32) {
+        if black_box(self.0) != black_box(expected) {
+            fault(TamperEvent::FlowMismatch);
+        }
+    }
+}
+
+/// Called when a hardened check finds an impossible state, which indicates a fault injection
+/// attack. Records the fault, then wipes every key and decrypted picture from RAM and resets.
+#[inline(never)]
+pub fn fault(event: TamperEvent) -> ! {
+    record_tamper(event);
+    wipe_and_reset()
+}
+
+/// Zero all of RAM, which holds every copy of a key or decrypted picture, then reset the system.
+pub fn wipe_and_reset() -> ! {
+    cortex_m::interrupt::disable();
+    // The stack guard would fault the wipe
+    disable_mpu();
+    // Safety: Nothing in RAM is read again before the reset, which reinitializes it
+    unsafe {
+        core::arch::asm!(
+            "2:",
+            "str {zero}, [{addr}], #4",
+            "cmp {addr}, {end}",
+            "blo 2b",
+            "dsb",
+            addr = inout(reg) RAM_START => _,
+            end = in(reg) RAM_END,
+            zero = in(reg) 0u32,
+            options(nostack),
+        );
+    }
+    SCB::sys_reset()
 }
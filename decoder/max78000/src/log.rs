@@ -0,0 +1,102 @@
+//! Logging to the host with Debug messages. Logging is only compiled in with the `debug-log`
+//! feature, so production images contain no log strings.
+#![cfg_attr(not(feature = "debug-log"), allow(dead_code))]
+
+/// The maximum length of a formatted log message, including the level prefix.
+pub const MAX_LOG_LENGTH: usize = 128;
+
+/// The severity of a log message.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    /// Returns the prefix which tells the host the level of a message.
+    pub fn prefix(&self) -> &'static [u8] {
+        match self {
+            Level::Error => b"E ",
+            Level::Warn => b"W ",
+            Level::Info => b"I ",
+            Level::Debug => b"D ",
+        }
+    }
+}
+
+/// Messages less severe than this are stripped at compile time.
+pub const MAX_LEVEL: Level = Level::Debug;
+
+/// A fixed-size buffer for formatting a log message, which truncates anything too long.
+pub struct LogBuffer {
+    data: [u8; MAX_LOG_LENGTH],
+    length: usize,
+}
+
+impl LogBuffer {
+    /// Create a buffer holding the prefix for the given level.
+    pub fn new(level: Level) -> Self {
+        let mut buffer = Self {
+            data: [0u8; MAX_LOG_LENGTH],
+            length: 0,
+        };
+        buffer.push(level.prefix());
+        buffer
+    }
+
+    /// Returns the formatted message.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.length]
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        let length = core::cmp::min(bytes.len(), MAX_LOG_LENGTH - self.length);
+        self.data[self.length..self.length + length].copy_from_slice(&bytes[..length]);
+        self.length += length;
+    }
+}
+
+impl core::fmt::Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Send a formatted message at the given level to the host, if logging is enabled.
+#[cfg(feature = "debug-log")]
+macro_rules! log {
+    ($host:expr, $level:expr, $($arg:tt)+) => {{
+        if $level <= $crate::log::MAX_LEVEL {
+            let mut buffer = $crate::log::LogBuffer::new($level);
+            let _ = core::fmt::Write::write_fmt(&mut buffer, format_args!($($arg)+));
+            $host.debug(buffer.as_bytes());
+        }
+    }};
+}
+
+/// Send a formatted message at the given level to the host, if logging is enabled.
+#[cfg(not(feature = "debug-log"))]
+macro_rules! log {
+    ($host:expr, $level:expr, $($arg:tt)+) => {{}};
+}
+
+macro_rules! log_error {
+    ($host:expr, $($arg:tt)+) => { $crate::log::log!($host, $crate::log::Level::Error, $($arg)+) };
+}
+
+macro_rules! log_warn {
+    ($host:expr, $($arg:tt)+) => { $crate::log::log!($host, $crate::log::Level::Warn, $($arg)+) };
+}
+
+macro_rules! log_info {
+    ($host:expr, $($arg:tt)+) => { $crate::log::log!($host, $crate::log::Level::Info, $($arg)+) };
+}
+
+macro_rules! log_debug {
+    ($host:expr, $($arg:tt)+) => { $crate::log::log!($host, $crate::log::Level::Debug, $($arg)+) };
+}
+
+pub(crate) use {log, log_debug, log_error, log_info, log_warn};
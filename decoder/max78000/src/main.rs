@@ -1,34 +1,74 @@
 #![no_std]
 #![no_main]
 
+pub mod attestation;
+pub mod auth_failures;
+pub mod crash_log;
 pub mod crypto;
 pub mod decode;
+pub mod delay;
+pub mod flash_lock;
+pub mod flash_log;
 pub mod hardening;
-pub mod host_driver;
+pub mod log;
+pub mod mpu;
+pub mod reset_counter;
 pub mod rng;
+pub mod stack;
 pub mod subscription;
+pub mod tamper;
+pub mod telemetry;
 pub mod tmr;
+pub mod uart_rx;
 
 pub extern crate max7800x_hal as hal;
 pub use hal::entry;
 pub use hal::pac;
 
-use panic_halt as _;
-
+use attestation::{attest, firmware_intact, init_measurement};
+use auth_failures::{
+    auth_locked_out, clear_auth_failures, init_auth_failures, record_auth_failure,
+};
+use bincode::encode_into_slice;
 use common::constants::*;
-use common::{MessageToDecoder, Timestamp};
-use decode::{decrypt_frame, validate_and_decrypt_picture};
-use host_driver::{HostDriver, Message};
+use common::{MessageToDecoder, Timestamp, BINCODE_CONFIG};
+#[cfg(feature = "crash-log")]
+use crash_log::{crash_count, init_crash_log, read_crash_log};
+use crypto::{get_attestation_key, get_identity_key, AsconCipher};
+use decode::{decode_frame, AuthOutcome};
+use delay::SysTickDelay;
+use embedded_hal_nb::serial;
+use flash_lock::{check_flash_lock, lock_flash};
+use hal::flc::Flc;
+use hardening::{pad_to_budget_us, FlowCounter};
+use host_driver::{BaudRate, HostDriver, Message, UartError};
+use log::{log_debug, log_error, log_info, log_warn};
+use mpu::init_mpu;
+use rand_chacha::ChaCha20Rng;
+use reset_counter::{init_reset_counter, record_reset_counter};
 use rng::init_global_rng;
 use rng::seed_rng;
-use subscription::{decrypt_subscription, list_subscriptions, update_subscription};
-use tmr::Tmr2;
+use stack::scrub_stack;
+#[cfg(feature = "stack-usage")]
+use stack::stack_high_water_mark;
+use subscription::{
+    decrypt_factory_reset, decrypt_subscription, erase_subscriptions, list_subscriptions,
+    update_subscription, SUBSCRIBE_STEPS,
+};
+use tamper::{init_tamper, tamper_locked_out};
+use telemetry::{record_subscription_update, reset_telemetry, take_telemetry};
+use tmr::{Tmr2, Tmr2Clock};
+use uart_rx::BufferedUart;
+use zeroize::Zeroize;
 
 #[entry]
 fn main() -> ! {
     let p = pac::Peripherals::take().expect("Failed to take peripherals");
     let core = pac::CorePeripherals::take().expect("Failed to take core peripherals");
 
+    // Protect RAM, the stack and the secrets before anything else runs
+    init_mpu(core.MPU);
+
     let mut gcr = hal::gcr::Gcr::new(p.gcr, p.lpgcr);
     let ipo = hal::gcr::clocks::Ipo::new(gcr.osc_guards.ipo).enable(&mut gcr.reg);
     let clks = gcr
@@ -39,18 +79,21 @@ fn main() -> ! {
 
     // Initialize a delay timer using the ARM SYST (SysTick) peripheral
     let rate = clks.sys_clk.frequency;
-    let host_delay = cortex_m::delay::Delay::new(core.SYST, rate);
+    let host_delay = SysTickDelay(cortex_m::delay::Delay::new(core.SYST, rate));
 
     // Initialize and split the GPIO0 peripheral into pins
     let gpio0_pins = hal::gpio::Gpio0::new(p.gpio0, &mut gcr.reg).split();
-    // Configure UART to host computer with 115200 8N1 settings
+    // Configure UART to host computer with 115200 8N1 settings, until the host negotiates a
+    // faster baud rate
     let rx_pin = gpio0_pins.p0_0.into_af1();
     let tx_pin = gpio0_pins.p0_1.into_af1();
     let host_uart = hal::uart::UartPeripheral::uart0(p.uart0, &mut gcr.reg, rx_pin, tx_pin)
-        .baud(115200)
+        .baud(DEFAULT_BAUD_RATE)
         .clock_pclk(&clks.pclk)
         .parity(hal::uart::ParityBit::None)
         .build();
+    // Receive from the host in the background so no bytes are lost while busy
+    let host_uart = BufferedUart::new(host_uart, clks.pclk.frequency);
 
     // Initialize TRNG peripheral
     let trng = hal::trng::Trng::new(p.trng, &mut gcr.reg);
@@ -58,65 +101,290 @@ fn main() -> ! {
     // Initialize TMR2 peripheral
     let tmr2 = Tmr2::new(p.tmr2, &mut gcr.reg);
     tmr2.config();
+    let host_clock = Tmr2Clock::new(&tmr2, clks.pclk.frequency);
+    let decode_clock = Tmr2Clock::new(&tmr2, clks.pclk.frequency);
+
+    // Load the persistent count of detected faults, before any hardened check can record one
+    init_tamper(clks.sys_clk);
+
+    // Find the crash log, so panics from here on are recorded
+    #[cfg(feature = "crash-log")]
+    init_crash_log(clks.sys_clk);
+
+    // Initialize the custom RNG, which the random delays in hardened checks draw from
+    let rng_seed = unsafe {
+        core::ptr::read_volatile(FLASH_ADDR_RANDOM_BYTES as *const [u8; LEN_RNG_SEED * 2])
+    };
+
+    let host_rng = seed_rng::<0x10>(&rng_seed[LEN_RNG_SEED..LEN_RNG_SEED * 2], &trng, &tmr2);
+
+    init_global_rng(&rng_seed[..LEN_RNG_SEED], trng, tmr2);
+
+    // Check the code region is intact before any key is read. The comparison is hardened, so
+    // this needs the RNG.
+    init_measurement();
 
     // Initialize the FLC peripheral
     let mut flc = hal::flc::Flc::new(p.flc, clks.sys_clk);
 
-    // Initialize the custom RNG
-    let rng_seed =
-        unsafe { core::ptr::read_volatile(FLASH_ADDR_RANDOM_BYTES as *const [u8; LEN_RNG_SEED * 2]) };
+    // Write-lock the firmware image, which never changes once provisioned
+    lock_flash(&flc);
+    check_flash_lock();
 
-    let host_rng = seed_rng::<0x10>(&rng_seed[LEN_RNG_SEED..LEN_RNG_SEED*2], &trng, &tmr2);
+    // Load the persistent authentication failure count, waiting out any back-off owed
+    init_auth_failures(&mut flc, rate);
 
-    init_global_rng(&rng_seed[..LEN_RNG_SEED], trng, tmr2);
+    // Load the counter of the last accepted factory reset, so old resets cannot be replayed
+    init_reset_counter(&mut flc);
 
     // Initialize the monotonic timestamp tracker
     let mut timestamp = Timestamp(0);
 
     // Iniitialize the host transport driver
-    let mut host = HostDriver::new(host_uart, host_rng, host_delay);
+    let mut host = HostDriver::new(host_uart, host_rng, host_delay, host_clock, AsconCipher);
+    log_info!(host, "Decoder booted");
+
+    // Paint the stack, so the stack used by the first command can be measured
+    scrub_stack();
+    #[cfg(feature = "stack-usage")]
+    let mut peak_stack_usage = 0;
 
     loop {
-        let message = host.read_message();
-        match message {
-            Ok(MessageToDecoder::ListSubscriptions) => {
-                let sub_list = list_subscriptions(&mut flc);
-                assert!(sub_list.num_sub_channels <= LEN_STANDARD_CHANNELS as u32);
-                let mut m = Message::list();
-                m.add_data(&sub_list.num_sub_channels.to_le_bytes());
-                for i in 0..sub_list.num_sub_channels {
-                    m.add_data(&sub_list.subscriptions[i as usize].channel_id.to_le_bytes());
-                    m.add_data(&sub_list.subscriptions[i as usize].start.to_le_bytes());
-                    m.add_data(&sub_list.subscriptions[i as usize].end.to_le_bytes());
+        handle_command(&mut host, &mut flc, &mut timestamp, &decode_clock);
+
+        #[cfg(feature = "stack-usage")]
+        {
+            let stack_usage = stack_high_water_mark();
+            peak_stack_usage = core::cmp::max(peak_stack_usage, stack_usage);
+            log_info!(
+                host,
+                "Stack used {} bytes, peak {} bytes",
+                stack_usage,
+                peak_stack_usage
+            );
+        }
+        // Wipe anything the command left on the stack
+        scrub_stack();
+    }
+}
+
+/// Read one command from the host and respond to it. This is never inlined, so everything the
+/// command leaves on the stack (decrypted pictures, response buffers, key fragments) is in a
+/// frame below `main`, which `scrub_stack` wipes once it returns.
+#[inline(never)]
+fn handle_command<Serial, SerialError>(
+    host: &mut HostDriver<Serial, ChaCha20Rng, SysTickDelay, Tmr2Clock, AsconCipher, SerialError>,
+    flc: &mut Flc,
+    timestamp: &mut Timestamp,
+    decode_clock: &Tmr2Clock,
+) where
+    Serial:
+        serial::Read<u8, Error = SerialError> + serial::Write<u8, Error = SerialError> + BaudRate,
+{
+    let message = host.read_message();
+    match message {
+        Ok(MessageToDecoder::ListSubscriptions { flags }) => {
+            let sub_list = list_subscriptions(flc);
+            assert!(sub_list.num_sub_channels <= LEN_STANDARD_CHANNELS as u32);
+            let subscriptions = &sub_list.subscriptions[..sub_list.num_sub_channels as usize];
+            // Each time window is reported as a separate entry
+            let num_entries: u32 = subscriptions
+                .iter()
+                .map(|sub| sub.windows().len() as u32)
+                .sum();
+            let mut m = Message::list();
+            m.add_data(&num_entries.to_le_bytes());
+            for sub in subscriptions {
+                for window in sub.windows() {
+                    m.add_data(&sub.channel_id.to_le_bytes());
+                    m.add_data(&window.start.to_le_bytes());
+                    m.add_data(&window.end.to_le_bytes());
+                    if flags & LIST_FLAG_STATUS != 0 {
+                        let mut status = 0u8;
+                        if window.end < timestamp.0 {
+                            status |= LIST_STATUS_EXPIRED;
+                        }
+                        m.add_data(&[status]);
+                    }
                 }
-                host.write_message(m);
             }
-            Ok(MessageToDecoder::UpdateSubscription(enc_subscription)) => {
-                match decrypt_subscription(enc_subscription) {
-                    Ok(new_sub) => match update_subscription(&mut flc, new_sub) {
-                        Ok(_) => host.write_message(Message::subscribe()),
-                        Err(_) => host.error(),
-                    },
-                    Err(_) => host.error(),
+            host.write_message(m);
+        }
+        // Nothing is decrypted once too many authentication failures have been recorded
+        Ok(
+            MessageToDecoder::UpdateSubscription(_)
+            | MessageToDecoder::FactoryReset(_)
+            | MessageToDecoder::DecodeFrame(_)
+            | MessageToDecoder::DecodeBatch(_),
+        ) if auth_locked_out() => {
+            log_error!(host, "Locked out after too many authentication failures");
+            host.error()
+        }
+        // Nor once too many faults have been detected, until the decoder is re-provisioned
+        Ok(
+            MessageToDecoder::UpdateSubscription(_)
+            | MessageToDecoder::FactoryReset(_)
+            | MessageToDecoder::DecodeFrame(_)
+            | MessageToDecoder::DecodeBatch(_),
+        ) if tamper_locked_out() => {
+            log_error!(host, "Locked out after detecting tampering");
+            host.error()
+        }
+        // No key is read if the code region does not match its measurement
+        Ok(
+            MessageToDecoder::UpdateSubscription(_)
+            | MessageToDecoder::FactoryReset(_)
+            | MessageToDecoder::DecodeFrame(_)
+            | MessageToDecoder::DecodeBatch(_)
+            | MessageToDecoder::Attest(_)
+            | MessageToDecoder::Connect(_),
+        ) if firmware_intact().is_false() => {
+            log_error!(host, "Firmware does not match its measurement");
+            host.error()
+        }
+        Ok(MessageToDecoder::UpdateSubscription(enc_subscription)) => {
+            let mut flow = FlowCounter::new();
+            match decrypt_subscription(enc_subscription, &mut flow) {
+                Ok(new_sub) => match update_subscription(flc, new_sub, timestamp.0) {
+                    Ok(_) => {
+                        flow.step();
+                        flow.verify(SUBSCRIBE_STEPS);
+                        record_subscription_update(true);
+                        host.write_message(Message::subscribe())
+                    }
+                    Err(_) => {
+                        record_subscription_update(false);
+                        log_error!(host, "Subscription could not be stored");
+                        host.error()
+                    }
+                },
+                Err(_) => {
+                    record_subscription_update(false);
+                    record_auth_failure(flc);
+                    log_warn!(host, "Subscription failed to decrypt");
+                    host.error()
                 }
             }
-            Ok(MessageToDecoder::DecodeFrame(enc_frame)) => {
-                match decrypt_frame(&enc_frame) {
-                    Ok(dec_frame) => {
-                        // TODO: Add random delay here
-                        match validate_and_decrypt_picture(&mut flc, &mut timestamp, &dec_frame) {
-                            Ok(pic) => {
-                                let mut m = Message::decode();
-                                m.add_data_bounded(&pic.picture.0, pic.picture_length as usize);
-                                host.write_message(m);
-                            }
-                            Err(_) => host.error(),
-                        }
+        }
+        Ok(MessageToDecoder::FactoryReset(enc_reset)) => match decrypt_factory_reset(enc_reset) {
+            Ok(counter) => match record_reset_counter(flc, counter)
+                .and_then(|()| erase_subscriptions(flc).map_err(|_| ()))
+            {
+                Ok(()) => {
+                    reset_telemetry();
+                    clear_auth_failures(flc);
+                    log_info!(host, "Factory reset");
+                    host.write_message(Message::factory_reset())
+                }
+                Err(_) => {
+                    log_error!(host, "Subscriptions could not be erased");
+                    host.error()
+                }
+            },
+            Err(_) => {
+                record_auth_failure(flc);
+                log_warn!(host, "Factory reset failed to decrypt or was replayed");
+                host.error()
+            }
+        },
+        Ok(MessageToDecoder::DecodeFrame(enc_frame)) => {
+            let result = pad_to_budget_us(decode_clock, DECODE_TIME_BUDGET_US, || {
+                decode_frame(flc, timestamp, &enc_frame)
+            });
+            let outcome = AuthOutcome::of(&result);
+            match result {
+                Ok(pic) => {
+                    let mut m = Message::decode();
+                    m.add_data_bounded(&pic.picture.0, pic.picture_length as usize);
+                    host.write_message(m);
+                }
+                Err(_) => {
+                    log_debug!(host, "Frame rejected, last timestamp {}", timestamp.0);
+                    host.error()
+                }
+            }
+            // Outside the padded decode, as it writes flash and may back off. The next command
+            // still waits for it, which the README describes under Decode Timing
+            outcome.record(flc);
+        }
+        Ok(MessageToDecoder::DecodeBatch(batch)) => {
+            // Frames are decoded in order, so each must be newer than the last valid one
+            let mut m = Message::decode_batch();
+            let mut outcomes = [AuthOutcome::Neither; MAX_BATCH_FRAMES];
+            for (enc_frame, outcome) in batch.frames().iter().zip(outcomes.iter_mut()) {
+                let result = pad_to_budget_us(decode_clock, DECODE_TIME_BUDGET_US, || {
+                    decode_frame(flc, timestamp, enc_frame)
+                });
+                *outcome = AuthOutcome::of(&result);
+                match result {
+                    Ok(pic) => {
+                        m.add_data(&[BATCH_STATUS_OK, pic.picture_length]);
+                        m.add_data_bounded(&pic.picture.0, pic.picture_length as usize);
                     }
-                    Err(_) => host.error(),
+                    Err(_) => m.add_data(&[BATCH_STATUS_ERROR, 0]),
+                }
+            }
+            host.write_message(m);
+            // Outside the padded decodes, as it writes flash and may back off
+            for outcome in outcomes {
+                outcome.record(flc);
+            }
+        }
+        // Telemetry says which check rejected each frame, which the padded decode hides, so only
+        // a host holding the session key may read or reset it
+        Ok(MessageToDecoder::Telemetry { .. }) if !host.is_encrypted() => {
+            log_warn!(host, "Telemetry requires an encrypted session");
+            host.error()
+        }
+        Ok(MessageToDecoder::Telemetry { flags }) => {
+            let telemetry = take_telemetry(flags & TELEMETRY_FLAG_RESET != 0);
+            let mut telemetry_bytes = [0u8; LEN_TELEMETRY];
+            match encode_into_slice(telemetry, &mut telemetry_bytes, BINCODE_CONFIG) {
+                Ok(LEN_TELEMETRY) => {
+                    let mut m = Message::telemetry();
+                    m.add_data(&telemetry_bytes);
+                    host.write_message(m);
                 }
+                _ => host.error(),
             }
-            Err(_) => host.error(),
-        };
+        }
+        #[cfg(feature = "crash-log")]
+        Ok(MessageToDecoder::CrashLog) => {
+            let mut m = Message::crash_log();
+            m.add_data(&crash_count().to_le_bytes());
+            read_crash_log(flc, |record| {
+                m.add_data(&record.line.to_le_bytes());
+                m.add_data(&record.column.to_le_bytes());
+                m.add_data(&record.file);
+            });
+            host.write_message(m);
+        }
+        #[cfg(not(feature = "crash-log"))]
+        Ok(MessageToDecoder::CrashLog) => host.error(),
+        Ok(MessageToDecoder::Attest(nonce)) => {
+            let response = attest(&nonce);
+            let mut m = Message::attest();
+            m.add_data(&response.decoder_id.to_le_bytes());
+            m.add_data(&response.measurement.0);
+            m.add_data(&response.mac.0);
+            host.write_message(m);
+        }
+        Ok(MessageToDecoder::Connect(request)) => {
+            let mut attestation_key = get_attestation_key();
+            let mut identity_key = get_identity_key();
+            host.connect(&request, &attestation_key, &identity_key);
+            attestation_key.zeroize();
+            identity_key.zeroize();
+        }
+        Ok(MessageToDecoder::SetBaudRate(baud_rate)) => {
+            log_info!(host, "Baud rate {} requested", baud_rate);
+            host.change_baud_rate(baud_rate)
+        }
+        // The host stopped responding, so wait for the next message
+        Err(UartError::Timeout) => log_debug!(host, "Host timed out"),
+        Err(_) => {
+            log_warn!(host, "Invalid message from host");
+            host.error()
+        }
     }
 }
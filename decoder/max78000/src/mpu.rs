@@ -0,0 +1,128 @@
+use crate::hardening::{wipe_and_reset, RAM_END, RAM_START};
+use crate::stack::{stack_guard_base, STACK_GUARD_SIZE};
+use common::constants::*;
+use cortex_m::asm::{dsb, isb};
+use cortex_m::peripheral::mpu::RegisterBlock;
+use cortex_m::peripheral::MPU;
+use cortex_m_rt::{exception, ExceptionFrame};
+
+// Regions are numbered by priority: where they overlap, the highest number wins. Memory outside
+// every region keeps the default memory map.
+// ┌────────┬──────────────────────┬───────────────────────────────────┐
+// │ Region │ Memory               │ Access                            │
+// ├────────┼──────────────────────┼───────────────────────────────────┤
+// │ 0      │ RAM                  │ Read/write, never executed        │
+// │ 1      │ Stack guard          │ None                              │
+// │ 2      │ Secrets flash page   │ Read-only while `with_secrets`    │
+// │        │                      │   runs, otherwise none            │
+// └────────┴──────────────────────┴───────────────────────────────────┘
+
+const REGION_RAM: u32 = 0;
+const REGION_STACK_GUARD: u32 = 1;
+const REGION_SECRETS: u32 = 2;
+
+/// The flash page holding the keys, decoder ID and firmware measurement.
+const SECRETS_START: u32 = FLASH_ADDR_FRAME_KEY & !(FLASH_PAGE_SIZE - 1);
+
+// Every secret must lie within the protected page
+const _: () = assert!(
+    FLASH_ADDR_SUBSCRIPTION_KEY >= SECRETS_START
+        && FLASH_ADDR_ATTESTATION_KEY >= SECRETS_START
+        && FLASH_ADDR_DECODER_ID >= SECRETS_START
+        && FLASH_ADDR_IDENTITY_KEY >= SECRETS_START
+        && FLASH_ADDR_FIRMWARE_MEASUREMENT + LEN_FIRMWARE_MEASUREMENT as u32
+            <= SECRETS_START + FLASH_PAGE_SIZE
+);
+
+// RASR fields
+const RASR_ENABLE: u32 = 1;
+const RASR_XN: u32 = 1 << 28;
+const RASR_AP_NONE: u32 = 0b000 << 24;
+const RASR_AP_READ_WRITE: u32 = 0b011 << 24;
+const RASR_AP_READ_ONLY: u32 = 0b110 << 24;
+// Normal memory, write-through, not shared
+const RASR_NORMAL: u32 = 1 << 17;
+
+// CTRL fields
+const CTRL_ENABLE: u32 = 1;
+const CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+/// Returns the SIZE field of RASR for a region of `size` bytes, which must be a power of two
+/// of at least 32.
+const fn rasr_size(size: u32) -> u32 {
+    assert!(size.is_power_of_two() && size >= 32);
+    (size.trailing_zeros() - 1) << 1
+}
+
+const RASR_SECRETS_LOCKED: u32 =
+    RASR_XN | RASR_AP_NONE | RASR_NORMAL | rasr_size(FLASH_PAGE_SIZE) | RASR_ENABLE;
+const RASR_SECRETS_UNLOCKED: u32 =
+    RASR_XN | RASR_AP_READ_ONLY | RASR_NORMAL | rasr_size(FLASH_PAGE_SIZE) | RASR_ENABLE;
+
+/// Set the base address and attributes of a region.
+fn set_region(mpu: &RegisterBlock, region: u32, base: u32, rasr: u32) {
+    // Safety: The regions never cover code which runs, and the caller synchronizes afterwards
+    unsafe {
+        mpu.rnr.write(region);
+        mpu.rbar.write(base);
+        mpu.rasr.write(rasr);
+    }
+}
+
+/// Configure and enable the MPU: RAM can never be executed, a guard below the stack faults on
+/// overflow, and the secrets page can only be read within `with_secrets`. Any
+/// access violation escalates to a HardFault, which wipes RAM and resets.
+pub fn init_mpu(mpu: MPU) {
+    set_region(
+        &mpu,
+        REGION_RAM,
+        RAM_START,
+        RASR_XN | RASR_AP_READ_WRITE | RASR_NORMAL | rasr_size(RAM_END - RAM_START) | RASR_ENABLE,
+    );
+    set_region(
+        &mpu,
+        REGION_STACK_GUARD,
+        stack_guard_base(),
+        RASR_XN | RASR_AP_NONE | RASR_NORMAL | rasr_size(STACK_GUARD_SIZE) | RASR_ENABLE,
+    );
+    set_region(&mpu, REGION_SECRETS, SECRETS_START, RASR_SECRETS_LOCKED);
+
+    // MemManage faults are left disabled, so they escalate to a HardFault. The MPU is off while
+    // a HardFault is handled, so its handler can still wipe the stack guard.
+    // Safety: Nothing running is in a region being protected
+    unsafe { mpu.ctrl.write(CTRL_PRIVDEFENA | CTRL_ENABLE) };
+    dsb();
+    isb();
+}
+
+/// Disable the MPU, so all of RAM can be wiped.
+pub fn disable_mpu() {
+    // Safety: Turning protection off never makes a valid access fault
+    unsafe { (*MPU::PTR).ctrl.write(0) };
+    dsb();
+    isb();
+}
+
+/// Run `f` with the secrets page readable, locking it again before returning. Interrupts are
+/// disabled meanwhile, so no handler can read the page.
+pub fn with_secrets<T>(f: impl FnOnce() -> T) -> T {
+    critical_section::with(|_| {
+        // Safety: The MPU is only reconfigured here and at boot, never concurrently
+        let mpu = unsafe { &*MPU::PTR };
+        set_region(mpu, REGION_SECRETS, SECRETS_START, RASR_SECRETS_UNLOCKED);
+        dsb();
+        isb();
+        let result = f();
+        set_region(mpu, REGION_SECRETS, SECRETS_START, RASR_SECRETS_LOCKED);
+        dsb();
+        isb();
+        result
+    })
+}
+
+/// A memory protection violation, stack overflow or other fault may have been induced to reach
+/// secrets, so it wipes RAM and resets.
+#[exception]
+unsafe fn HardFault(_frame: &ExceptionFrame) -> ! {
+    wipe_and_reset()
+}
@@ -0,0 +1,78 @@
+use crate::flash_log::FlashLog;
+use common::constants::*;
+use core::cell::Cell;
+use critical_section::Mutex;
+use hal::flc::Flc;
+
+// Every factory reset carries a counter, and the decoder only accepts one higher than that of
+// every reset it accepted before, so a captured reset cannot be replayed. The counter page is a
+// flash log with one record per accepted reset, holding its counter (8B) and the complement
+// (8B). The page is never erased, so the counter can never go back. A record which was partly
+// written when power was lost is ignored, as the reset it belonged to never erased anything.
+// Once the page is full, no more resets are accepted.
+
+const LOG: FlashLog = FlashLog {
+    addr: FLASH_ADDR_RESET_COUNTER,
+    magic: FLASH_MAGIC_RESET_COUNTER,
+    first_record: 16,
+    record_len: 16,
+    max_records: MAX_FACTORY_RESETS,
+};
+
+/// The highest counter accepted so far, and the number of records written.
+static LAST_RESET_COUNTER: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+static RESET_RECORDS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Returns the record storing `counter`.
+fn make_record(counter: u64) -> [u8; 16] {
+    let mut record = [0u8; 16];
+    record[..8].copy_from_slice(&counter.to_le_bytes());
+    record[8..].copy_from_slice(&(!counter).to_le_bytes());
+    record
+}
+
+/// Returns the counter stored in `record`, or None if it was not completely written.
+fn parse_record(record: &[u8; 16]) -> Option<u64> {
+    let counter = u64::from_le_bytes(record[..8].try_into().unwrap());
+    let complement = u64::from_le_bytes(record[8..].try_into().unwrap());
+    (counter == !complement).then_some(counter)
+}
+
+/// Load the highest accepted reset counter, initializing the counter page on first boot.
+pub fn init_reset_counter(flc: &mut Flc) {
+    let records = LOG.init(flc);
+    let mut last = 0;
+    for idx in 0..records {
+        let mut record = [0u8; 16];
+        if LOG.read(flc, idx, &mut record).is_ok() {
+            if let Some(counter) = parse_record(&record) {
+                last = core::cmp::max(last, counter);
+            }
+        }
+    }
+    critical_section::with(|cs| {
+        LAST_RESET_COUNTER.borrow(cs).set(last);
+        RESET_RECORDS.borrow(cs).set(records);
+    });
+}
+
+/// Returns the highest counter of any factory reset accepted so far.
+pub fn last_reset_counter() -> u64 {
+    critical_section::with(|cs| LAST_RESET_COUNTER.borrow(cs).get())
+}
+
+/// Persistently record that the factory reset with `counter` was accepted, before anything is
+/// erased. Fails if the page is full or the write fails, in which case the reset must not go
+/// ahead.
+pub fn record_reset_counter(flc: &mut Flc, counter: u64) -> Result<(), ()> {
+    let records = critical_section::with(|cs| RESET_RECORDS.borrow(cs).get());
+    if records >= MAX_FACTORY_RESETS {
+        return Err(());
+    }
+    // A failed write still takes up its record
+    critical_section::with(|cs| RESET_RECORDS.borrow(cs).set(records + 1));
+    LOG.append(flc, records, &make_record(counter))
+        .map_err(|_| ())?;
+    critical_section::with(|cs| LAST_RESET_COUNTER.borrow(cs).set(counter));
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use crate::telemetry::record_rng_reseed;
 use crate::tmr::Tmr2;
 use core::{
     cell::{OnceCell, RefCell},
@@ -39,6 +40,11 @@ extern "C" fn randombytes(bytes: *mut cty::c_uchar, len: cty::c_ulonglong) {
     });
 }
 
+/// Get a random u32 from the global RNG.
+pub fn random_u32() -> u32 {
+    critical_section::with(|cs| GLOBAL_RNG.borrow(cs).get().unwrap().borrow_mut().next_u32())
+}
+
 /// Rng which uses a reseeding process to gather fresh entropy from Trng occasionally
 pub struct CustomRng {
     rng: ChaCha20Rng,
@@ -74,6 +80,7 @@ impl CustomRng {
     fn reseed(&mut self) {
         let prev_seed = &self.rng.get_seed();
         self.rng = seed_rng::<8>(prev_seed, &self.trng, &self.tmr2);
+        record_rng_reseed();
     }
 
     fn check_reseed(&mut self) {
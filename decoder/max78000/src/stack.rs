@@ -0,0 +1,67 @@
+#![cfg_attr(not(feature = "stack-usage"), allow(dead_code))]
+
+use core::ptr::addr_of;
+
+/// The pattern the unused stack is painted with.
+const STACK_PAINT: u32 = 0xA5A5_A5A5;
+
+extern "C" {
+    // The lowest and highest addresses of the stack, from the cortex-m-rt linker script
+    static _stack_end: u32;
+    static _stack_start: u32;
+}
+
+/// Size of the guard region below the stack, the smallest the MPU supports.
+pub const STACK_GUARD_SIZE: u32 = 32;
+
+/// Returns the start of the guard region below the stack, which the MPU makes inaccessible so
+/// an overflow faults instead of overwriting statics.
+pub fn stack_guard_base() -> u32 {
+    (addr_of!(_stack_end) as u32).next_multiple_of(STACK_GUARD_SIZE)
+}
+
+/// Returns the lowest address the stack can grow down to, just above its guard.
+fn stack_bottom() -> u32 {
+    stack_guard_base() + STACK_GUARD_SIZE
+}
+
+/// Returns the address the stack starts from.
+fn stack_top() -> u32 {
+    addr_of!(_stack_start) as u32
+}
+
+/// Paint every byte of the stack below the stack pointer, which holds whatever the last command
+/// left behind (key fragments, decrypted data, Ascon state), with `STACK_PAINT`. Variables in
+/// the frames of the callers are untouched.
+#[inline(always)]
+pub fn scrub_stack() {
+    // Safety: Only memory below the stack pointer is written, which no live frame uses. An
+    // interrupt pushes its frame below the stack pointer too, but the loop does not run while
+    // the handler does.
+    unsafe {
+        core::arch::asm!(
+            "2:",
+            "cmp {addr}, sp",
+            "bhs 3f",
+            "str {paint}, [{addr}], #4",
+            "b 2b",
+            "3:",
+            addr = inout(reg) stack_bottom() => _,
+            paint = in(reg) STACK_PAINT,
+            options(nostack),
+        );
+    }
+}
+
+/// Returns the most stack used, in bytes, since the stack was last scrubbed. The deepest point
+/// reached is the lowest word which no longer holds the paint.
+pub fn stack_high_water_mark() -> u32 {
+    let mut addr = stack_bottom();
+    // Safety: Only words within the stack region are read
+    while addr < stack_top()
+        && unsafe { core::ptr::read_volatile(addr as *const u32) } == STACK_PAINT
+    {
+        addr += 4;
+    }
+    stack_top() - addr
+}
@@ -1,10 +1,14 @@
-use crate::crypto::{decrypt_ascon, get_subscription_key};
+use crate::crypto::{decrypt_ascon, get_decoder_id, get_subscription_key};
 use crate::hal::flc::{FlashError, Flc};
+use crate::hardening::{check_twice, FlowCounter, HardenedBool};
+use crate::reset_counter::last_reset_counter;
+use crate::telemetry::record_integrity_failure;
 use bincode::decode_from_slice;
 use common::constants::*;
 use common::{
-    check_complement_16b, make_complement_16b, ChannelSecret, EncryptedSubscription,
-    StoredSubscription, SubscriptionInfo, SubscriptionInfoList, BINCODE_CONFIG,
+    check_complement_16b, make_complement_16b, ChannelSecret, EncryptedFactoryReset,
+    EncryptedSubscription, FactoryReset, MergePolicy, StoredSubscription, SubscriptionInfo,
+    SubscriptionInfoList, SubscriptionUpdate, TimeWindow, BINCODE_CONFIG,
 };
 use zeroize::Zeroize;
 
@@ -16,16 +20,29 @@ use zeroize::Zeroize;
 // │Magic (4B), Chan. ID (4B)  │
 // │~Magic (4B), ~Chan. ID (4B)│
 // │~Magic (4B), ~Chan. ID (4B)│
-// │Start Timestamp (8B)       │
-// │End Timestamp (8B)         │
-// │~Start Timestamp (8B)      │
-// │~End Timestamp (8B)        │
+// │Num. Windows (4B) x4       │
+// │~Num. Windows (4B) x4      │
 // │Channel Secret 1/2 (16B)   │
 // │~Channel Secret 1/2 (16B)  │
 // │Channel Secret 2/2 (16B)   │
 // │~Channel Secret 2/2 (16B)  │
+// │Window 1 Start (8B)        │
+// │Window 1 End (8B)          │
+// │~Window 1 Start (8B)       │
+// │~Window 1 End (8B)         │
+// │...                        │
+// │Window N Start (8B)        │
+// │Window N End (8B)          │
+// │~Window N Start (8B)       │
+// │~Window N End (8B)         │
 // └───────────────────────────┘
 
+const OFFSET_HEADER: u32 = 0;
+const OFFSET_NUM_WINDOWS: u32 = 32;
+const OFFSET_CHANNEL_SECRET: u32 = 64;
+const OFFSET_WINDOWS: u32 = 128;
+const LEN_WINDOW_RECORD: u32 = 32;
+
 /// Helper function to write 16 bytes to flash
 pub fn write_16b(flc: &mut Flc, addr: u32, data: &[u8; 16]) -> Result<(), FlashError> {
     let data_u32: [u32; 4] = [
@@ -53,60 +70,224 @@ pub fn read_16b(flc: &mut Flc, addr: u32, data: &mut [u8; 16]) -> Result<(), Fla
     Ok(())
 }
 
-/// Decrypts the subscription and returns a StoredSubscription.
+/// The error types that can be encountered while updating a subscription
+pub enum UpdateError {
+    Flash(FlashError),
+    MergeRejected,
+    EmergencyChannel,
+}
+
+impl From<FlashError> for UpdateError {
+    fn from(e: FlashError) -> Self {
+        UpdateError::Flash(e)
+    }
+}
+
+/// The number of steps on the success path of a subscription update, verified before the
+/// update is acknowledged. Two are counted by `decrypt_subscription` and one by the caller once
+/// the subscription is stored.
+pub const SUBSCRIBE_STEPS: u32 = 3;
+
+/// Decrypts the subscription update and returns a SubscriptionUpdate.
+/// Each passed check is counted in `flow`.
 pub fn decrypt_subscription(
     enc_subscription: EncryptedSubscription,
-) -> Result<StoredSubscription, ()> {
-    let mut dec_sub_bytes = [0u8; LEN_STORED_SUBSCRIPTION];
+    flow: &mut FlowCounter,
+) -> Result<SubscriptionUpdate, ()> {
+    let mut dec_sub_bytes = [0u8; LEN_SUBSCRIPTION_UPDATE];
 
     let mut subscription_key = get_subscription_key();
-    match decrypt_ascon(&enc_subscription.0, &subscription_key.0, &mut dec_sub_bytes) {
-        Ok(LEN_STORED_SUBSCRIPTION) => {}
+    let decrypted = HardenedBool::new(matches!(
+        decrypt_ascon(&enc_subscription.0, &subscription_key.0, &mut dec_sub_bytes),
+        Ok(LEN_SUBSCRIPTION_UPDATE)
+    ));
+    subscription_key.zeroize();
+    if decrypted.is_false() {
+        return Err(());
+    }
+    flow.step();
+    let dec_sub: SubscriptionUpdate = match decode_from_slice(&dec_sub_bytes, BINCODE_CONFIG) {
+        Ok((sub, LEN_SUBSCRIPTION_UPDATE)) => sub,
         _ => return Err(()),
     };
+    dec_sub_bytes.zeroize();
+    let is_valid = check_twice(|| dec_sub.subscription.info.is_valid());
+    if is_valid.is_false() {
+        return Err(());
+    }
+    flow.step();
+    decrypted.assert_true();
+    is_valid.assert_true();
+    Ok(dec_sub)
+}
+
+/// Decrypts a factory reset request and checks that it is meant for this decoder and is newer
+/// than every reset accepted before. Returns the counter of the reset, which must be recorded
+/// before anything is erased.
+pub fn decrypt_factory_reset(enc_reset: EncryptedFactoryReset) -> Result<u64, ()> {
+    let mut dec_reset_bytes = [0u8; LEN_FACTORY_RESET];
+
+    let mut subscription_key = get_subscription_key();
+    let decrypted = HardenedBool::new(matches!(
+        decrypt_ascon(&enc_reset.0, &subscription_key.0, &mut dec_reset_bytes),
+        Ok(LEN_FACTORY_RESET)
+    ));
     subscription_key.zeroize();
-    let dec_sub: StoredSubscription = match decode_from_slice(&dec_sub_bytes, BINCODE_CONFIG) {
-        Ok((sub, LEN_STORED_SUBSCRIPTION)) => sub,
+    if decrypted.is_false() {
+        return Err(());
+    }
+    let dec_reset: FactoryReset = match decode_from_slice(&dec_reset_bytes, BINCODE_CONFIG) {
+        Ok((reset, LEN_FACTORY_RESET)) => reset,
         _ => return Err(()),
     };
-    Ok(dec_sub)
+    let is_for_decoder = check_twice(|| {
+        dec_reset.magic == FACTORY_RESET_MAGIC && dec_reset.decoder_id == get_decoder_id()
+    });
+    if is_for_decoder.is_false() {
+        return Err(());
+    }
+    let is_fresh = check_twice(|| dec_reset.counter > last_reset_counter());
+    if is_fresh.is_false() {
+        return Err(());
+    }
+    decrypted.assert_true();
+    is_for_decoder.assert_true();
+    is_fresh.assert_true();
+    Ok(dec_reset.counter)
+}
+
+/// Erases every standard channel subscription from flash memory. The emergency channel
+/// subscription written by the firmware builder is kept.
+pub fn erase_subscriptions(flc: &mut Flc) -> Result<(), FlashError> {
+    for idx in 1..=LEN_STANDARD_CHANNELS as u32 {
+        let sub_addr: u32 = FLASH_ADDR_SUBSCRIPTION_BASE + (idx * FLASH_PAGE_SIZE);
+        unsafe {
+            flc.erase_page(sub_addr)?;
+        }
+    }
+    Ok(())
+}
+
+/// Combines the time windows of an existing subscription with those of an update, according
+/// to the merge policy of the update. Overlapping and adjacent windows are coalesced.
+/// Returns an error if the policy rejects the update or the result has too many windows.
+fn merge_subscription_info(
+    existing: &SubscriptionInfo,
+    update: &SubscriptionInfo,
+    policy: MergePolicy,
+) -> Result<SubscriptionInfo, ()> {
+    match policy {
+        MergePolicy::Replace => return Ok(*update),
+        MergePolicy::RejectOnOverlap => {
+            for a in existing.windows() {
+                for b in update.windows() {
+                    if a.start <= b.end && b.start <= a.end {
+                        return Err(());
+                    }
+                }
+            }
+        }
+        MergePolicy::ExtendUnion => {}
+    }
+
+    // Collect all windows and sort them by start timestamp
+    let mut all_windows = [TimeWindow::default(); 2 * MAX_SUBSCRIPTION_WINDOWS];
+    let mut num_all_windows = 0;
+    for window in existing.windows().iter().chain(update.windows()) {
+        all_windows[num_all_windows] = *window;
+        num_all_windows += 1;
+    }
+    all_windows[..num_all_windows].sort_unstable_by_key(|w| w.start);
+
+    let mut merged = SubscriptionInfo {
+        channel_id: update.channel_id,
+        ..Default::default()
+    };
+    for window in &all_windows[..num_all_windows] {
+        let n = merged.num_windows as usize;
+        if n > 0 && window.start <= merged.windows[n - 1].end.saturating_add(1) {
+            let last = &mut merged.windows[n - 1];
+            last.end = core::cmp::max(last.end, window.end);
+        } else if n < MAX_SUBSCRIPTION_WINDOWS {
+            merged.windows[n] = *window;
+            merged.num_windows += 1;
+        } else {
+            return Err(());
+        }
+    }
+
+    Ok(merged)
 }
 
 /// Updates the given subscription in flash memory.
 /// - Iterates through the available flash pages.
-/// - If a subscription is found with the same channel ID, it is replaced.
-/// - If an empty or invalid subscription is found, the new subscription is written.
+/// - If a subscription is found with the same channel ID, it is merged with the update
+///   according to the update's merge policy and rewritten.
+/// - Otherwise, the new subscription is written to the first empty or invalid slot.
+/// - If there are no empty slots, the first slot holding an expired subscription (one whose
+///   windows all end before `high_water_mark`) is reclaimed. The high-water mark is the timestamp
+///   of the last decoded frame, which is only kept in RAM, so until a frame has been decoded
+///   since boot no subscription counts as expired and no slot is reclaimed.
 /// - If there are no more slots available, the subscription is not written and an error is returned.
-pub fn update_subscription(flc: &mut Flc, new_sub: StoredSubscription) -> Result<(), FlashError> {
-    assert!(
-        new_sub.info.channel_id != EMERGENCY_CHANNEL_ID,
-        "Invalid channel ID"
-    );
+pub fn update_subscription(
+    flc: &mut Flc,
+    update: SubscriptionUpdate,
+    high_water_mark: u64,
+) -> Result<(), UpdateError> {
+    let new_sub = &update.subscription;
+    // The emergency channel subscription is written by the firmware builder and never changes
+    if new_sub.info.channel_id == EMERGENCY_CHANNEL_ID {
+        return Err(UpdateError::EmergencyChannel);
+    }
 
+    let mut free_idx = None;
+    let mut expired_idx = None;
     for idx in 1..=LEN_STANDARD_CHANNELS as u32 {
         match get_subscription_at_idx(flc, idx) {
             Ok(sub) => {
-                // If the channel ID matches, replace the subscription
+                // If the channel ID matches, merge with the existing subscription
                 if sub.info.channel_id == new_sub.info.channel_id {
-                    return write_subscription(flc, idx, new_sub);
+                    let info = merge_subscription_info(&sub.info, &new_sub.info, update.policy)
+                        .map_err(|_| UpdateError::MergeRejected)?;
+                    return Ok(write_subscription(
+                        flc,
+                        idx,
+                        &info,
+                        &new_sub.channel_secret,
+                    )?);
+                }
+                // Remember the first expired subscription, in case no slots are free
+                if expired_idx.is_none() && sub.info.is_expired(high_water_mark) {
+                    expired_idx = Some(idx);
                 }
             }
             Err(_) => {
-                // If the subscription is invalid, write the new subscription
-                return write_subscription(flc, idx, new_sub);
+                // Remember the first empty or invalid slot
+                if free_idx.is_none() {
+                    free_idx = Some(idx);
+                }
             }
         }
     }
 
-    // If we get here, there are no more slots available
-    Err(FlashError::NeedsErase)
+    match free_idx.or(expired_idx) {
+        Some(idx) => Ok(write_subscription(
+            flc,
+            idx,
+            &new_sub.info,
+            &new_sub.channel_secret,
+        )?),
+        // If we get here, there are no more slots available
+        None => Err(UpdateError::Flash(FlashError::NeedsErase)),
+    }
 }
 
 /// Writes the given subscription to flash memory at the given index.
 fn write_subscription(
     flc: &mut Flc,
     idx: u32,
-    new_sub: StoredSubscription,
+    info: &SubscriptionInfo,
+    channel_secret: &ChannelSecret,
 ) -> Result<(), FlashError> {
     let sub_addr: u32 = FLASH_ADDR_SUBSCRIPTION_BASE + (idx * FLASH_PAGE_SIZE);
 
@@ -116,48 +297,105 @@ fn write_subscription(
 
     // Write the header
     let mut header_bytes = [FLASH_MAGIC_SUBSCRIPTION; 16];
-    header_bytes[4..8].copy_from_slice(&new_sub.info.channel_id.to_le_bytes());
-    header_bytes[12..16].copy_from_slice(&new_sub.info.channel_id.to_le_bytes());
-    write_16b(flc, sub_addr, &header_bytes)?;
-    write_16b(flc, sub_addr + 16, &make_complement_16b(&header_bytes))?;
-
-    // Write the timestamps
-    let mut timestamp_bytes = [0u8; 16];
-    timestamp_bytes[0..8].copy_from_slice(&new_sub.info.start.to_le_bytes());
-    timestamp_bytes[8..16].copy_from_slice(&new_sub.info.end.to_le_bytes());
-    write_16b(flc, sub_addr + 32, &timestamp_bytes)?;
-    write_16b(flc, sub_addr + 48, &make_complement_16b(&timestamp_bytes))?;
+    header_bytes[4..8].copy_from_slice(&info.channel_id.to_le_bytes());
+    header_bytes[12..16].copy_from_slice(&info.channel_id.to_le_bytes());
+    write_16b(flc, sub_addr + OFFSET_HEADER, &header_bytes)?;
+    write_16b(
+        flc,
+        sub_addr + OFFSET_HEADER + 16,
+        &make_complement_16b(&header_bytes),
+    )?;
+
+    // Write the number of windows
+    let windows = info.windows();
+    let mut num_windows_bytes = [0u8; 16];
+    for i in 0..4 {
+        num_windows_bytes[i * 4..(i + 1) * 4]
+            .copy_from_slice(&(windows.len() as u32).to_le_bytes());
+    }
+    write_16b(flc, sub_addr + OFFSET_NUM_WINDOWS, &num_windows_bytes)?;
+    write_16b(
+        flc,
+        sub_addr + OFFSET_NUM_WINDOWS + 16,
+        &make_complement_16b(&num_windows_bytes),
+    )?;
 
     // Write the channel secret
     let mut channel_secret_bytes_1 = [0u8; 16];
-    channel_secret_bytes_1.copy_from_slice(&new_sub.channel_secret.0[0..16]);
-    write_16b(flc, sub_addr + 64, &channel_secret_bytes_1)?;
+    channel_secret_bytes_1.copy_from_slice(&channel_secret.0[0..16]);
+    write_16b(
+        flc,
+        sub_addr + OFFSET_CHANNEL_SECRET,
+        &channel_secret_bytes_1,
+    )?;
     write_16b(
         flc,
-        sub_addr + 80,
+        sub_addr + OFFSET_CHANNEL_SECRET + 16,
         &make_complement_16b(&channel_secret_bytes_1),
     )?;
     channel_secret_bytes_1.zeroize();
     let mut channel_secret_bytes_2 = [0u8; 16];
-    channel_secret_bytes_2.copy_from_slice(&new_sub.channel_secret.0[16..32]);
-    write_16b(flc, sub_addr + 96, &channel_secret_bytes_2)?;
+    channel_secret_bytes_2.copy_from_slice(&channel_secret.0[16..32]);
     write_16b(
         flc,
-        sub_addr + 112,
+        sub_addr + OFFSET_CHANNEL_SECRET + 32,
+        &channel_secret_bytes_2,
+    )?;
+    write_16b(
+        flc,
+        sub_addr + OFFSET_CHANNEL_SECRET + 48,
         &make_complement_16b(&channel_secret_bytes_2),
     )?;
     channel_secret_bytes_2.zeroize();
 
+    // Write the time windows
+    for (i, window) in windows.iter().enumerate() {
+        let window_addr = sub_addr + OFFSET_WINDOWS + (i as u32 * LEN_WINDOW_RECORD);
+        let mut timestamp_bytes = [0u8; 16];
+        timestamp_bytes[0..8].copy_from_slice(&window.start.to_le_bytes());
+        timestamp_bytes[8..16].copy_from_slice(&window.end.to_le_bytes());
+        write_16b(flc, window_addr, &timestamp_bytes)?;
+        write_16b(
+            flc,
+            window_addr + 16,
+            &make_complement_16b(&timestamp_bytes),
+        )?;
+    }
+
     Ok(())
 }
 
 /// Gets the subscription at the given index in flash.
-/// Performs integrity checks on the stored subscription to ensure it is valid.
+/// Performs integrity checks on the stored subscription to ensure it is valid, counting a
+/// failure in the telemetry unless the slot is simply empty.
 pub fn get_subscription_at_idx(flc: &mut Flc, idx: u32) -> Result<StoredSubscription, ()> {
     if idx as usize > LEN_STANDARD_CHANNELS {
         return Err(());
     }
 
+    let result = read_subscription_at_idx(flc, idx);
+    if result.is_err() && !is_slot_erased(flc, idx) {
+        record_integrity_failure();
+    }
+    result
+}
+
+/// Returns true if the header of the subscription slot at the given index is erased.
+fn is_slot_erased(flc: &mut Flc, idx: u32) -> bool {
+    let sub_addr: u32 = FLASH_ADDR_SUBSCRIPTION_BASE + (idx * FLASH_PAGE_SIZE);
+    let mut header_bytes = [0u8; 16];
+    for offset in [OFFSET_HEADER, OFFSET_HEADER + 16] {
+        if read_16b(flc, sub_addr + offset, &mut header_bytes).is_err()
+            || header_bytes != [0xFF; 16]
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// Reads the subscription at the given index in flash, checking its integrity.
+fn read_subscription_at_idx(flc: &mut Flc, idx: u32) -> Result<StoredSubscription, ()> {
     let sub_addr: u32 = FLASH_ADDR_SUBSCRIPTION_BASE + (idx * FLASH_PAGE_SIZE);
 
     // Shared complement bytes
@@ -165,8 +403,8 @@ pub fn get_subscription_at_idx(flc: &mut Flc, idx: u32) -> Result<StoredSubscrip
 
     // Validate magic bytes, channel ID, magic bytes, channel ID
     let mut header_bytes = [0u8; 16];
-    read_16b(flc, sub_addr, &mut header_bytes).unwrap();
-    read_16b(flc, sub_addr + 16, &mut complement_bytes).unwrap();
+    read_16b(flc, sub_addr + OFFSET_HEADER, &mut header_bytes).map_err(|_| ())?;
+    read_16b(flc, sub_addr + OFFSET_HEADER + 16, &mut complement_bytes).map_err(|_| ())?;
     if !check_complement_16b(&header_bytes, &complement_bytes) {
         return Err(());
     }
@@ -184,29 +422,82 @@ pub fn get_subscription_at_idx(flc: &mut Flc, idx: u32) -> Result<StoredSubscrip
         return Err(());
     }
 
-    // Read the timestamps
-    let mut timestamp_bytes = [0u8; 16];
-    read_16b(flc, sub_addr + 32, &mut timestamp_bytes).unwrap();
-    read_16b(flc, sub_addr + 48, &mut complement_bytes).unwrap();
-    if !check_complement_16b(&timestamp_bytes, &complement_bytes) {
+    // Read the number of windows
+    let mut num_windows_bytes = [0u8; 16];
+    read_16b(flc, sub_addr + OFFSET_NUM_WINDOWS, &mut num_windows_bytes).map_err(|_| ())?;
+    read_16b(
+        flc,
+        sub_addr + OFFSET_NUM_WINDOWS + 16,
+        &mut complement_bytes,
+    )
+    .map_err(|_| ())?;
+    if !check_complement_16b(&num_windows_bytes, &complement_bytes) {
+        return Err(());
+    }
+    let num_windows: u32 = u32::from_le_bytes(num_windows_bytes[0..4].try_into().unwrap());
+    for i in 1..4 {
+        let num_windows_temp: u32 =
+            u32::from_le_bytes(num_windows_bytes[i * 4..(i + 1) * 4].try_into().unwrap());
+        if num_windows != num_windows_temp {
+            return Err(());
+        }
+    }
+    if num_windows == 0 || num_windows as usize > MAX_SUBSCRIPTION_WINDOWS {
         return Err(());
     }
-    let start: u64 = u64::from_le_bytes(timestamp_bytes[0..8].try_into().unwrap());
-    let end: u64 = u64::from_le_bytes(timestamp_bytes[8..16].try_into().unwrap());
-    if start > end {
+
+    // Read the time windows
+    let mut windows = [TimeWindow::default(); MAX_SUBSCRIPTION_WINDOWS];
+    for (i, window) in windows.iter_mut().take(num_windows as usize).enumerate() {
+        let window_addr = sub_addr + OFFSET_WINDOWS + (i as u32 * LEN_WINDOW_RECORD);
+        let mut timestamp_bytes = [0u8; 16];
+        read_16b(flc, window_addr, &mut timestamp_bytes).map_err(|_| ())?;
+        read_16b(flc, window_addr + 16, &mut complement_bytes).map_err(|_| ())?;
+        if !check_complement_16b(&timestamp_bytes, &complement_bytes) {
+            return Err(());
+        }
+        window.start = u64::from_le_bytes(timestamp_bytes[0..8].try_into().unwrap());
+        window.end = u64::from_le_bytes(timestamp_bytes[8..16].try_into().unwrap());
+    }
+    let info = SubscriptionInfo {
+        channel_id,
+        num_windows: num_windows as u8,
+        windows,
+    };
+    if !info.is_valid() {
         return Err(());
     }
 
     // Read the channel secret
     let mut channel_secret_bytes_1 = [0u8; 16];
-    read_16b(flc, sub_addr + 64, &mut channel_secret_bytes_1).unwrap();
-    read_16b(flc, sub_addr + 80, &mut complement_bytes).unwrap();
+    read_16b(
+        flc,
+        sub_addr + OFFSET_CHANNEL_SECRET,
+        &mut channel_secret_bytes_1,
+    )
+    .map_err(|_| ())?;
+    read_16b(
+        flc,
+        sub_addr + OFFSET_CHANNEL_SECRET + 16,
+        &mut complement_bytes,
+    )
+    .map_err(|_| ())?;
     if !check_complement_16b(&channel_secret_bytes_1, &complement_bytes) {
         return Err(());
     }
     let mut channel_secret_bytes_2 = [0u8; 16];
-    read_16b(flc, sub_addr + 96, &mut channel_secret_bytes_2).unwrap();
-    read_16b(flc, sub_addr + 112, &mut complement_bytes).unwrap();
+    read_16b(
+        flc,
+        sub_addr + OFFSET_CHANNEL_SECRET + 32,
+        &mut channel_secret_bytes_2,
+    )
+    .map_err(|_| ())?;
+    read_16b(
+        flc,
+        sub_addr + OFFSET_CHANNEL_SECRET + 48,
+        &mut complement_bytes,
+    )
+    .map_err(|_| ())?;
     if !check_complement_16b(&channel_secret_bytes_2, &complement_bytes) {
         return Err(());
     }
@@ -222,11 +513,7 @@ pub fn get_subscription_at_idx(flc: &mut Flc, idx: u32) -> Result<StoredSubscrip
 
     // Construct the stored subscription
     let stored_sub = StoredSubscription {
-        info: SubscriptionInfo {
-            channel_id,
-            start,
-            end,
-        },
+        info,
         channel_secret: ChannelSecret(channel_secret_bytes),
     };
 
@@ -251,11 +538,7 @@ pub fn get_channel_subscription(flc: &mut Flc, channel_id: u32) -> Result<Stored
 
 /// Returns a list of all valid subscriptions in flash.
 pub fn list_subscriptions(flc: &mut Flc) -> SubscriptionInfoList {
-    let mut subscriptions = core::array::from_fn(|_| SubscriptionInfo {
-        channel_id: 0,
-        start: 0,
-        end: 0,
-    });
+    let mut subscriptions = core::array::from_fn(|_| SubscriptionInfo::default());
 
     let mut num_sub_channels: usize = 0;
     for idx in 1..=LEN_STANDARD_CHANNELS as u32 {
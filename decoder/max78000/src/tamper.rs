@@ -0,0 +1,111 @@
+use crate::flash_log::FlashLog;
+use crate::subscription::{read_16b, write_16b};
+use common::constants::*;
+use core::cell::{Cell, RefCell};
+use core::hint::black_box;
+use critical_section::Mutex;
+use hal::flc::Flc;
+use hal::gcr::clocks::{Clock, SystemClock};
+use hal::pac;
+
+// The tamper page is a flash log with one record per detected fault. The provisioning ID of the
+// firmware image which initialized it is stored after the header. Re-provisioning changes the
+// provisioning ID, so the page is reinitialized on the next boot.
+
+const OFFSET_PROVISIONING_ID: u32 = 16;
+const LOG: FlashLog = FlashLog {
+    addr: FLASH_ADDR_TAMPER_EVENTS,
+    magic: FLASH_MAGIC_TAMPER_EVENTS,
+    first_record: 32,
+    record_len: 16,
+    max_records: MAX_TAMPER_EVENTS,
+};
+
+/// The inconsistencies which indicate a fault injection attack. Each record is filled with the
+/// value of the event which caused it.
+#[derive(Clone, Copy)]
+#[repr(u8)]
+pub enum TamperEvent {
+    /// A check made twice gave different results
+    CheckMismatch = 0x01,
+    /// A hardened value held neither of its valid patterns
+    InvalidState = 0x02,
+    /// A check which failed was passed anyway
+    SkippedCheck = 0x03,
+    /// A command took the wrong number of steps
+    FlowMismatch = 0x04,
+    /// Code which cannot be reached was reached
+    Unreachable = 0x05,
+    /// A page of the firmware image could still be written, or a page the firmware writes could not
+    FlashUnlocked = 0x06,
+}
+
+static TAMPER_FLC: Mutex<RefCell<Option<Flc>>> = Mutex::new(RefCell::new(None));
+static TAMPER_EVENTS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+/// Get the provisioning ID of the running firmware image from flash memory.
+fn get_provisioning_id() -> [u8; LEN_PROVISIONING_ID] {
+    unsafe {
+        core::ptr::read_volatile(FLASH_ADDR_PROVISIONING_ID as *const [u8; LEN_PROVISIONING_ID])
+    }
+}
+
+/// Returns the number of faults recorded in the tamper page, or None if it has not been
+/// initialized by this firmware image.
+fn count_records(flc: &mut Flc) -> Option<u32> {
+    let count = LOG.count(flc)?;
+    let mut provisioning_id = [0u8; LEN_PROVISIONING_ID];
+    let provisioning_id_addr = FLASH_ADDR_TAMPER_EVENTS + OFFSET_PROVISIONING_ID;
+    if read_16b(flc, provisioning_id_addr, &mut provisioning_id).is_err()
+        || provisioning_id != get_provisioning_id()
+    {
+        return None;
+    }
+    Some(count)
+}
+
+/// Load the persistent fault count, initializing the tamper page on first boot after
+/// provisioning. Keeps a flash controller of its own, so faults can be recorded from anywhere.
+pub fn init_tamper(sys_clk: Clock<SystemClock>) {
+    // Safety: This flash controller is only used here and when recording a fault, neither of
+    // which happens during another flash operation
+    let mut flc = Flc::new(unsafe { pac::Flc::steal() }, sys_clk);
+
+    let count = match count_records(&mut flc) {
+        Some(count) => count,
+        None => {
+            LOG.reset(&mut flc).expect("Failed to erase tamper page");
+            write_16b(
+                &mut flc,
+                FLASH_ADDR_TAMPER_EVENTS + OFFSET_PROVISIONING_ID,
+                &get_provisioning_id(),
+            )
+            .expect("Failed to write tamper page");
+            0
+        }
+    };
+    critical_section::with(|cs| {
+        TAMPER_EVENTS.borrow(cs).set(count);
+        TAMPER_FLC.borrow(cs).replace(Some(flc));
+    });
+}
+
+/// Returns true if so many faults have been detected that the decoder refuses to decrypt
+/// anything until it is re-provisioned.
+pub fn tamper_locked_out() -> bool {
+    let count = critical_section::with(|cs| TAMPER_EVENTS.borrow(cs).get());
+    black_box(count) >= black_box(TAMPER_LOCKOUT)
+}
+
+/// Persistently record a detected fault.
+pub fn record_tamper(event: TamperEvent) {
+    critical_section::with(|cs| {
+        let count = TAMPER_EVENTS.borrow(cs).get();
+        if let Some(flc) = TAMPER_FLC.borrow(cs).borrow_mut().as_mut() {
+            // A failed write still counts, and nothing is written once the page is full
+            let _ = LOG.append(flc, count, &[event as u8; 16]);
+        }
+        let count = core::cmp::min(count + 1, MAX_TAMPER_EVENTS);
+        TAMPER_EVENTS.borrow(cs).set(count);
+    });
+}
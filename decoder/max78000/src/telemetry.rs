@@ -0,0 +1,88 @@
+use common::constants::TELEMETRY_CHANNELS;
+use common::Telemetry;
+use core::cell::RefCell;
+use critical_section::Mutex;
+
+static TELEMETRY: Mutex<RefCell<Telemetry>> = Mutex::new(RefCell::new(Telemetry::new()));
+
+/// The reasons a frame can be rejected, each counted separately.
+pub enum FrameRejection {
+    InvalidFrame,
+    NoSubscription,
+    OutsideWindow,
+    StaleTimestamp,
+    InvalidPicture,
+}
+
+/// Helper function to update the counters.
+fn update(f: impl FnOnce(&mut Telemetry)) {
+    critical_section::with(|cs| f(&mut TELEMETRY.borrow_ref_mut(cs)));
+}
+
+/// Count a frame decoded on the given channel.
+pub fn record_frame_decoded(channel_id: u32) {
+    update(|t| {
+        let num_channels = t.num_channels as usize;
+        match t.frames_decoded[..num_channels]
+            .iter_mut()
+            .find(|c| c.channel_id == channel_id)
+        {
+            Some(count) => count.frames = count.frames.saturating_add(1),
+            None if num_channels < TELEMETRY_CHANNELS => {
+                t.frames_decoded[num_channels].channel_id = channel_id;
+                t.frames_decoded[num_channels].frames = 1;
+                t.num_channels += 1;
+            }
+            None => t.frames_decoded_untracked = t.frames_decoded_untracked.saturating_add(1),
+        }
+    });
+}
+
+/// Count a frame rejected for the given reason.
+pub fn record_frame_rejected(reason: &FrameRejection) {
+    update(|t| {
+        let counter = match reason {
+            FrameRejection::InvalidFrame => &mut t.frames_rejected.invalid_frame,
+            FrameRejection::NoSubscription => &mut t.frames_rejected.no_subscription,
+            FrameRejection::OutsideWindow => &mut t.frames_rejected.outside_window,
+            FrameRejection::StaleTimestamp => &mut t.frames_rejected.stale_timestamp,
+            FrameRejection::InvalidPicture => &mut t.frames_rejected.invalid_picture,
+        };
+        *counter = counter.saturating_add(1);
+    });
+}
+
+/// Count a subscription update which was accepted or rejected.
+pub fn record_subscription_update(accepted: bool) {
+    update(|t| match accepted {
+        true => t.subscriptions_accepted = t.subscriptions_accepted.saturating_add(1),
+        false => t.subscriptions_rejected = t.subscriptions_rejected.saturating_add(1),
+    });
+}
+
+/// Count a stored subscription which failed its integrity checks.
+pub fn record_integrity_failure() {
+    update(|t| t.integrity_failures = t.integrity_failures.saturating_add(1));
+}
+
+/// Count a reseed of the global RNG.
+pub fn record_rng_reseed() {
+    update(|t| t.rng_reseeds = t.rng_reseeds.saturating_add(1));
+}
+
+/// Reset all counters to zero.
+pub fn reset_telemetry() {
+    update(|t| *t = Telemetry::new());
+}
+
+/// Returns a copy of the counters, resetting them if requested.
+pub fn take_telemetry(reset: bool) -> Telemetry {
+    critical_section::with(|cs| {
+        let mut telemetry = TELEMETRY.borrow_ref_mut(cs);
+        let snapshot = *telemetry;
+        if reset {
+            *telemetry = Telemetry::new();
+        }
+        snapshot
+    })
+}
@@ -1,5 +1,6 @@
 use hal::gcr::{ClockForPeripheral, GcrRegisters};
 use hal::pac;
+use host_driver::Clock;
 
 pub struct Tmr2 {
     tmr: pac::Tmr2,
@@ -67,3 +68,29 @@ impl Tmr2 {
         while self.tmr.ctrl0().read().clken_a().bit_is_clear() {}
     }
 }
+
+/// A read-only view of the TMR2 counter, for measuring elapsed time while the peripheral
+/// itself is owned by the RNG.
+pub struct Tmr2Clock {
+    ticks_per_ms: u32,
+}
+
+impl Tmr2Clock {
+    /// Create a clock from a configured TMR2, which counts at the PCLK frequency.
+    pub fn new(_tmr2: &Tmr2, pclk_frequency: u32) -> Self {
+        Self {
+            ticks_per_ms: pclk_frequency / 1_000,
+        }
+    }
+}
+
+impl Clock for Tmr2Clock {
+    fn ticks(&self) -> u32 {
+        // Safety: Only the count register is read, which has no side effects
+        unsafe { (*pac::Tmr2::ptr()).cnt().read().bits() }
+    }
+
+    fn ticks_per_ms(&self) -> u32 {
+        self.ticks_per_ms
+    }
+}
@@ -0,0 +1,172 @@
+use core::cell::RefCell;
+use cortex_m::peripheral::NVIC;
+use cortex_m_rt::interrupt;
+use critical_section::Mutex;
+use embedded_hal_nb::{nb, serial};
+use hal::pac;
+use hal::pac::Interrupt as interrupt;
+use hal::uart::BuiltUartPeripheral;
+use host_driver::{BaudRate, BYTE_TIMEOUT_MS};
+
+/// Size of the receive ring buffer, enough for several full messages from the host.
+const RX_BUFFER_SIZE: usize = 0x1000;
+
+static RX_BUFFER: Mutex<RefCell<RingBuffer>> = Mutex::new(RefCell::new(RingBuffer::new()));
+
+/// A fixed-size FIFO of received bytes, filled by the UART0 interrupt handler.
+struct RingBuffer {
+    data: [u8; RX_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+    overruns: u32,
+}
+
+impl RingBuffer {
+    const fn new() -> Self {
+        Self {
+            data: [0u8; RX_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+            overruns: 0,
+        }
+    }
+
+    /// Add a byte to the buffer, dropping it if the buffer is full.
+    fn push(&mut self, byte: u8) {
+        if self.len == RX_BUFFER_SIZE {
+            self.overruns = self.overruns.wrapping_add(1);
+            return;
+        }
+        self.data[(self.head + self.len) % RX_BUFFER_SIZE] = byte;
+        self.len += 1;
+    }
+
+    /// Remove the oldest byte from the buffer, if any.
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.data[self.head];
+        self.head = (self.head + 1) % RX_BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    /// Discard all buffered bytes.
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+/// Drains the UART0 receive FIFO into the ring buffer whenever a byte arrives, so nothing is
+/// lost while the main loop is busy (e.g. during random delays or flash writes).
+#[interrupt]
+fn UART0() {
+    // Safety: The handler only reads the receive FIFO and clears receive flags, which the
+    // transmit path never touches
+    let uart = unsafe { &*pac::Uart0::ptr() };
+    critical_section::with(|cs| {
+        let mut buffer = RX_BUFFER.borrow_ref_mut(cs);
+        while uart.status().read().rx_em().bit_is_clear() {
+            buffer.push(uart.fifo().read().data().bits());
+        }
+        if uart.int_fl().read().rx_ov().bit_is_set() {
+            buffer.overruns = buffer.overruns.wrapping_add(1);
+        }
+    });
+    uart.int_fl()
+        .write(|w| w.rx_thd().set_bit().rx_ov().set_bit());
+}
+
+/// Returns the number of received bytes dropped because a buffer was full.
+pub fn rx_overruns() -> u32 {
+    critical_section::with(|cs| RX_BUFFER.borrow_ref(cs).overruns)
+}
+
+/// A serial interface to UART0 which reads from the interrupt-driven ring buffer and writes
+/// directly to the UART.
+pub struct BufferedUart<Uart> {
+    uart: Uart,
+    clock_frequency: u32,
+}
+
+impl<RX, TX, CTS, RTS> BufferedUart<BuiltUartPeripheral<pac::Uart0, RX, TX, CTS, RTS>> {
+    /// Start interrupt-driven reception on UART0, which must already be configured to run from
+    /// a clock of `clock_frequency` Hz.
+    pub fn new(
+        uart: BuiltUartPeripheral<pac::Uart0, RX, TX, CTS, RTS>,
+        clock_frequency: u32,
+    ) -> Self {
+        // Safety: Only the receive interrupt configuration is changed
+        let regs = unsafe { &*pac::Uart0::ptr() };
+        // Interrupt as soon as a single byte is received
+        regs.ctrl().modify(|_, w| unsafe { w.rx_thd_val().bits(1) });
+        regs.int_fl()
+            .write(|w| w.rx_thd().set_bit().rx_ov().set_bit());
+        regs.int_en()
+            .modify(|_, w| w.rx_thd().set_bit().rx_ov().set_bit());
+        // Safety: The handler only accesses the ring buffer within a critical section
+        unsafe { NVIC::unmask(interrupt::UART0) };
+        Self {
+            uart,
+            clock_frequency,
+        }
+    }
+}
+
+impl<Uart> serial::ErrorType for BufferedUart<Uart>
+where
+    Uart: serial::ErrorType,
+{
+    type Error = Uart::Error;
+}
+
+impl<Uart> serial::Read<u8> for BufferedUart<Uart>
+where
+    Uart: serial::ErrorType,
+{
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        critical_section::with(|cs| RX_BUFFER.borrow_ref_mut(cs).pop()).ok_or(nb::Error::WouldBlock)
+    }
+}
+
+impl<Uart> serial::Write<u8> for BufferedUart<Uart>
+where
+    Uart: serial::Write<u8>,
+{
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        self.uart.write(byte)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.uart.flush()
+    }
+}
+
+impl<RX, TX, CTS, RTS> BaudRate
+    for BufferedUart<BuiltUartPeripheral<pac::Uart0, RX, TX, CTS, RTS>>
+{
+    fn set_baud_rate(&mut self, baud_rate: u32) {
+        // The HAL cannot turn a built UART back into a UartPeripheral to rebuild it, so the
+        // divisor is reprogrammed the way build() does, with the baud clock stopped meanwhile
+        // Safety: Only the baud clock configuration of the UART owned by self is changed
+        let regs = unsafe { &*pac::Uart0::ptr() };
+        regs.ctrl().modify(|_, w| w.bclken().clear_bit());
+        regs.clkdiv()
+            .write(|w| unsafe { w.clkdiv().bits(self.clock_frequency / baud_rate) });
+        regs.ctrl().modify(|_, w| w.bclken().set_bit());
+        // Give up after BYTE_TIMEOUT_MS rather than hang. The host then cannot confirm the new
+        // rate, so the driver switches back to the previous one
+        let cycles_per_us = self.clock_frequency / 1_000_000;
+        for _ in 0..BYTE_TIMEOUT_MS * 1_000 {
+            if regs.ctrl().read().bclkrdy().bit_is_set() {
+                break;
+            }
+            cortex_m::asm::delay(cycles_per_us);
+        }
+
+        // Anything received during the switch was garbled
+        critical_section::with(|cs| RX_BUFFER.borrow_ref_mut(cs).clear());
+    }
+}
@@ -0,0 +1 @@
+from .rust import measure_firmware, verify_attestation
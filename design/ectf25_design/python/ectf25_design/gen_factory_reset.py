@@ -0,0 +1,42 @@
+from .rust import gen_factory_reset
+import argparse
+import time
+from pathlib import Path
+
+def parse_args():
+    parser = argparse.ArgumentParser()
+    parser.add_argument(
+        "--force",
+        "-f",
+        action="store_true",
+        help="Force creation of factory reset file, overwriting existing file",
+    )
+    parser.add_argument(
+        "secrets_file",
+        type=argparse.FileType("rb"),
+        help="Path to the secrets file created by ectf25_design.gen_secrets",
+    )
+    parser.add_argument("reset_file", type=Path, help="Factory reset output")
+    parser.add_argument(
+        "device_id", type=lambda x: int(x, 0), help="Device ID of the Decoder to reset."
+    )
+    parser.add_argument(
+        "--counter",
+        "-c",
+        type=lambda x: int(x, 0),
+        default=None,
+        help="Reset counter, which must be higher than that of every reset the Decoder accepted"
+        " before. Defaults to the current time in microseconds.",
+    )
+    return parser.parse_args()
+
+def main():
+    args = parse_args()
+    counter = args.counter if args.counter is not None else time.time_ns() // 1000
+    reset = gen_factory_reset(args.secrets_file.read(), args.device_id, counter)
+    with open(args.reset_file, "wb" if args.force else "xb") as f:
+        f.write(reset)
+    print(f"Wrote factory reset to {str(args.reset_file.absolute())}")
+
+if __name__ == "__main__":
+    main()
@@ -1,4 +1,4 @@
-from .rust import gen_subscription
+from .rust import gen_subscription, gen_subscription_windows
 import argparse
 from pathlib import Path
 
@@ -24,13 +24,37 @@ def parse_args():
     )
     parser.add_argument("end", type=int, help="Subscription end timestamp")
     parser.add_argument("channel", type=int, help="Channel to subscribe to")
+    parser.add_argument(
+        "--window",
+        "-w",
+        nargs=2,
+        action="append",
+        default=[],
+        metavar=("START", "END"),
+        type=lambda x: int(x, 0),
+        help="Additional time window for the subscription (may be repeated)",
+    )
+    parser.add_argument(
+        "--merge",
+        "-m",
+        choices=["replace", "union", "reject-overlap"],
+        default="replace",
+        help="How the Decoder combines this subscription with an existing subscription"
+        " for the same channel",
+    )
     return parser.parse_args()
 
 def main():
     args = parse_args()
-    subscription = gen_subscription(
-        args.secrets_file.read(), args.device_id, args.start, args.end, args.channel
-    )
+    if args.window or args.merge != "replace":
+        windows = [(args.start, args.end)] + [tuple(w) for w in args.window]
+        subscription = gen_subscription_windows(
+            args.secrets_file.read(), args.device_id, windows, args.channel, args.merge
+        )
+    else:
+        subscription = gen_subscription(
+            args.secrets_file.read(), args.device_id, args.start, args.end, args.channel
+        )
     with open(args.subscription_file, "wb" if args.force else "xb") as f:
         f.write(subscription)
     print(f"Wrote subscription to {str(args.subscription_file.absolute())}")
@@ -0,0 +1 @@
+from .rust import Session
@@ -17,3 +17,29 @@ pub fn encrypt_ascon(data: &[u8], key_bytes: &[u8; LEN_ASCON_KEY]) -> Vec<u8> {
     output.extend_from_slice(&ciphertext);
     output
 }
+
+// Returns encrypted data for the given nonce, without prepending it
+pub fn encrypt_ascon_with_nonce(
+    data: &[u8],
+    nonce_bytes: &[u8; LEN_ASCON_NONCE],
+    key_bytes: &[u8; LEN_ASCON_KEY],
+) -> Vec<u8> {
+    let key = Key::<Ascon128>::from_slice(key_bytes);
+    let nonce = Nonce::<Ascon128>::from_slice(nonce_bytes);
+    let cipher = Ascon128::new(key);
+
+    cipher.encrypt(nonce, data).expect("Encryption failure!")
+}
+
+// Returns decrypted data for the given nonce, or None if the ciphertext is invalid
+pub fn decrypt_ascon_with_nonce(
+    data: &[u8],
+    nonce_bytes: &[u8; LEN_ASCON_NONCE],
+    key_bytes: &[u8; LEN_ASCON_KEY],
+) -> Option<Vec<u8>> {
+    let key = Key::<Ascon128>::from_slice(key_bytes);
+    let nonce = Nonce::<Ascon128>::from_slice(nonce_bytes);
+    let cipher = Ascon128::new(key);
+
+    cipher.decrypt(nonce, data).ok()
+}
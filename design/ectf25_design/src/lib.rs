@@ -1,11 +1,16 @@
 mod crypto;
+mod session;
 use crypto::encrypt_ascon;
 
 use common::constants::*;
-use common::crypto::{derive_channel_secret, derive_picture_key, derive_subscription_key};
+use common::crypto::{
+    compute_attestation_mac, derive_attestation_key, derive_channel_secret, derive_picture_key,
+    derive_subscription_key, measure_firmware as measure_code,
+};
 use common::{
-    BaseChannelSecret, BaseSubscriptionSecret, DecryptedFrame, DeploymentSecrets, EncryptedPicture,
-    FrameKey, StoredSubscription, SubscriptionInfo, BINCODE_CONFIG,
+    AttestationNonce, AttestationResponse, BaseChannelSecret, BaseSubscriptionSecret,
+    DecryptedFrame, DeploymentSecrets, EncryptedPicture, FactoryReset, FrameKey, MergePolicy,
+    StoredSubscription, SubscriptionInfo, SubscriptionUpdate, TimeWindow, BINCODE_CONFIG,
 };
 use pyo3::prelude::*;
 use rand::Rng;
@@ -35,9 +40,34 @@ fn gen_subscription(
     start: u64,
     end: u64,
     channel: u32,
+) -> Vec<u8> {
+    gen_subscription_windows(secrets, device_id, vec![(start, end)], channel, "replace")
+}
+
+/// Generate a subscription for a given device ID, list of time windows, and channel.
+///
+/// The merge policy determines how the decoder combines this subscription with an existing
+/// subscription for the same channel: "replace", "union", or "reject-overlap".
+#[pyfunction]
+#[pyo3(signature = (secrets, device_id, windows, channel, policy="replace"))]
+fn gen_subscription_windows(
+    secrets: Vec<u8>,
+    device_id: u32,
+    mut windows: Vec<(u64, u64)>,
+    channel: u32,
+    policy: &str,
 ) -> Vec<u8> {
     assert!(channel != EMERGENCY_CHANNEL_ID, "Invalid channel");
-    assert!(start <= end, "Invalid time range");
+    assert!(
+        !windows.is_empty() && windows.len() <= MAX_SUBSCRIPTION_WINDOWS,
+        "Invalid number of time windows"
+    );
+    let policy = match policy {
+        "replace" => MergePolicy::Replace,
+        "union" => MergePolicy::ExtendUnion,
+        "reject-overlap" => MergePolicy::RejectOnOverlap,
+        _ => panic!("Invalid merge policy"),
+    };
 
     // Deserialize the deployment secrets
     let s: DeploymentSecrets =
@@ -48,24 +78,33 @@ fn gen_subscription(
     let subscription_key = derive_subscription_key(&s.base_subscription_secret, device_id);
 
     // Initialize the subscription info
-    let subscription_info = SubscriptionInfo {
+    windows.sort();
+    let mut subscription_info = SubscriptionInfo {
         channel_id: channel,
-        start,
-        end,
+        num_windows: windows.len() as u8,
+        windows: [TimeWindow::default(); MAX_SUBSCRIPTION_WINDOWS],
     };
-    let stored_subscription = StoredSubscription {
-        info: subscription_info,
-        channel_secret,
+    for (i, (start, end)) in windows.into_iter().enumerate() {
+        assert!(start <= end, "Invalid time range");
+        subscription_info.windows[i] = TimeWindow { start, end };
+    }
+    assert!(subscription_info.is_valid(), "Time windows must be disjoint");
+    let subscription_update = SubscriptionUpdate {
+        policy,
+        subscription: StoredSubscription {
+            info: subscription_info,
+            channel_secret,
+        },
     };
 
     // Encode the subscription
-    let mut subscription_bytes = [0u8; LEN_STORED_SUBSCRIPTION];
+    let mut subscription_bytes = [0u8; LEN_SUBSCRIPTION_UPDATE];
     match bincode::encode_into_slice(
-        &stored_subscription,
+        &subscription_update,
         &mut subscription_bytes,
         BINCODE_CONFIG,
     ) {
-        Ok(LEN_STORED_SUBSCRIPTION) => (),
+        Ok(LEN_SUBSCRIPTION_UPDATE) => (),
         _ => panic!("Failed to encode subscription"),
     }
 
@@ -79,6 +118,90 @@ fn gen_subscription(
     encrypted_subscription
 }
 
+/// Generate a factory reset request for a given device ID, which erases all of the decoder's
+/// standard channel subscriptions. The decoder only accepts a `counter` higher than that of every
+/// reset it accepted before.
+#[pyfunction]
+fn gen_factory_reset(secrets: Vec<u8>, device_id: u32, counter: u64) -> Vec<u8> {
+    // Deserialize the deployment secrets
+    let s: DeploymentSecrets =
+        serde_json::from_slice(&secrets).expect("Failed to deserialize secrets");
+    // Derive the subscription encryption key for the given decoder ID
+    let subscription_key = derive_subscription_key(&s.base_subscription_secret, device_id);
+
+    // Encode the factory reset
+    let factory_reset = FactoryReset {
+        magic: FACTORY_RESET_MAGIC,
+        decoder_id: device_id,
+        counter,
+    };
+    let mut factory_reset_bytes = [0u8; LEN_FACTORY_RESET];
+    match bincode::encode_into_slice(&factory_reset, &mut factory_reset_bytes, BINCODE_CONFIG) {
+        Ok(LEN_FACTORY_RESET) => (),
+        _ => panic!("Failed to encode factory reset"),
+    }
+
+    // Encrypt the factory reset
+    let encrypted_factory_reset = encrypt_ascon(&factory_reset_bytes, &subscription_key.0);
+    assert_eq!(
+        encrypted_factory_reset.len(),
+        LEN_ENCRYPTED_FACTORY_RESET,
+        "Invalid encrypted factory reset length"
+    );
+    encrypted_factory_reset
+}
+
+/// Compute the firmware measurement of a built decoder image, as reported during attestation.
+#[pyfunction]
+fn measure_firmware(firmware: Vec<u8>) -> Vec<u8> {
+    assert!(
+        firmware.len() >= FLASH_FIRMWARE_CODE_SIZE as usize,
+        "Firmware image is too small"
+    );
+    let measurement = measure_code(&firmware[..FLASH_FIRMWARE_CODE_SIZE as usize]);
+    measurement.0.to_vec()
+}
+
+/// Verify a decoder's response to an attestation challenge.
+///
+/// Returns true if the response was produced by a decoder provisioned with the given device
+/// ID for this deployment, and (if given) reports the expected firmware measurement.
+#[pyfunction]
+#[pyo3(signature = (secrets, device_id, nonce, response, expected_measurement=None))]
+fn verify_attestation(
+    secrets: Vec<u8>,
+    device_id: u32,
+    nonce: Vec<u8>,
+    response: Vec<u8>,
+    expected_measurement: Option<Vec<u8>>,
+) -> bool {
+    let nonce = AttestationNonce(nonce.try_into().expect("Invalid nonce length"));
+    if response.len() != LEN_ATTESTATION_RESPONSE {
+        return false;
+    }
+    let decoded = bincode::decode_from_slice(&response, BINCODE_CONFIG);
+    let response: AttestationResponse = match decoded {
+        Ok((r, LEN_ATTESTATION_RESPONSE)) => r,
+        _ => return false,
+    };
+    if response.decoder_id != device_id {
+        return false;
+    }
+    if let Some(expected) = expected_measurement {
+        if response.measurement.0[..] != expected[..] {
+            return false;
+        }
+    }
+
+    // Deserialize the deployment secrets
+    let s: DeploymentSecrets =
+        serde_json::from_slice(&secrets).expect("Failed to deserialize secrets");
+    // Derive the attestation key for the given decoder ID
+    let attestation_key = derive_attestation_key(&s.base_subscription_secret, device_id);
+    let mac = compute_attestation_mac(&attestation_key, &nonce, device_id, &response.measurement);
+    mac == response.mac
+}
+
 #[pyclass]
 struct Encoder {
     secrets: DeploymentSecrets,
@@ -147,7 +270,12 @@ impl Encoder {
 fn rust(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(gen_secrets, m)?)?;
     m.add_function(wrap_pyfunction!(gen_subscription, m)?)?;
+    m.add_function(wrap_pyfunction!(gen_subscription_windows, m)?)?;
+    m.add_function(wrap_pyfunction!(gen_factory_reset, m)?)?;
+    m.add_function(wrap_pyfunction!(measure_firmware, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_attestation, m)?)?;
     m.add_class::<Encoder>()?;
+    m.add_class::<session::Session>()?;
 
     Ok(())
 }
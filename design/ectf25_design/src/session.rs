@@ -0,0 +1,179 @@
+use crate::crypto::{decrypt_ascon_with_nonce, encrypt_ascon_with_nonce};
+use common::constants::*;
+use common::crypto::{
+    compute_response_mac, derive_attestation_key, derive_identity_key, derive_session_cipher_key,
+    derive_session_key, make_session_nonce,
+};
+use common::{AttestationKey, DeploymentSecrets, SessionCipherKey, SessionKey, SessionNonce};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use rand::Rng;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Host side of a decoder session, used to negotiate optional protocol features and to
+/// protect messages once they are enabled.
+#[pyclass]
+pub struct Session {
+    attestation_key: AttestationKey,
+    identity_public_key: PublicKey,
+    #[pyo3(get)]
+    requested_flags: u8,
+    host_nonce: SessionNonce,
+    host_secret: StaticSecret,
+    key: Option<SessionKey>,
+    cipher_key: Option<SessionCipherKey>,
+    flags: u8,
+    tx_sequence: u32,
+    rx_sequence: u32,
+}
+
+#[pymethods]
+impl Session {
+    /// Initialize a session for the decoder with the given device ID.
+    #[new]
+    #[pyo3(signature = (secrets, device_id, flags=CONNECT_FLAG_RESPONSE_AUTH))]
+    fn new(secrets: Vec<u8>, device_id: u32, flags: u8) -> Self {
+        let s: DeploymentSecrets =
+            serde_json::from_slice(&secrets).expect("Failed to deserialize deployment secrets");
+        let identity_key = derive_identity_key(&s.base_subscription_secret, device_id);
+        Session {
+            attestation_key: derive_attestation_key(&s.base_subscription_secret, device_id),
+            identity_public_key: PublicKey::from(&StaticSecret::from(identity_key.0)),
+            requested_flags: flags,
+            host_nonce: SessionNonce([0u8; LEN_SESSION_NONCE]),
+            host_secret: StaticSecret::from([0u8; LEN_X25519_KEY]),
+            key: None,
+            cipher_key: None,
+            flags: 0,
+            tx_sequence: 0,
+            rx_sequence: 0,
+        }
+    }
+
+    /// Build the body of the Connect message which starts a new session.
+    fn connect_request(&mut self) -> Vec<u8> {
+        let mut rng = rand::rng();
+        self.host_nonce = SessionNonce(rng.random::<[u8; LEN_SESSION_NONCE]>());
+        self.host_secret = StaticSecret::from(rng.random::<[u8; LEN_X25519_KEY]>());
+        self.key = None;
+        self.cipher_key = None;
+        self.flags = 0;
+        self.tx_sequence = 0;
+        self.rx_sequence = 0;
+
+        let mut request = vec![self.requested_flags];
+        request.extend_from_slice(&self.host_nonce.0);
+        request.extend_from_slice(PublicKey::from(&self.host_secret).as_bytes());
+        request
+    }
+
+    /// Process the body of the decoder's Connect response. Returns the accepted flags.
+    fn accept(&mut self, body: Vec<u8>) -> PyResult<u8> {
+        if body.len() < LEN_CONNECT_RESPONSE {
+            return Err(PyValueError::new_err("Invalid connect response length"));
+        }
+        // The request is not authenticated, so a decoder granting anything other than what was
+        // requested may be a man-in-the-middle stripping protection from the session
+        let flags = body[0];
+        if flags != self.expected_flags() {
+            return Err(PyValueError::new_err(
+                "Decoder did not accept exactly the requested flags",
+            ));
+        }
+        let nonce_end = LEN_CONNECT_FLAGS + LEN_SESSION_NONCE;
+        let decoder_nonce = SessionNonce(body[LEN_CONNECT_FLAGS..nonce_end].try_into().unwrap());
+        let decoder_public_key: [u8; LEN_X25519_KEY] =
+            body[nonce_end..LEN_CONNECT_RESPONSE].try_into().unwrap();
+
+        // Shared secrets with the decoder's ephemeral key and its identity key
+        let mut key_exchange = Vec::new();
+        if flags & CONNECT_FLAG_ENCRYPTION != 0 {
+            let ephemeral_shared = self
+                .host_secret
+                .diffie_hellman(&PublicKey::from(decoder_public_key));
+            let identity_shared = self.host_secret.diffie_hellman(&self.identity_public_key);
+            key_exchange.extend_from_slice(ephemeral_shared.as_bytes());
+            key_exchange.extend_from_slice(identity_shared.as_bytes());
+        }
+        if flags & (CONNECT_FLAG_RESPONSE_AUTH | CONNECT_FLAG_ENCRYPTION) != 0 {
+            let key = derive_session_key(
+                &self.attestation_key,
+                self.requested_flags,
+                &self.host_nonce,
+                &decoder_nonce,
+                &key_exchange,
+            );
+            self.cipher_key = Some(derive_session_cipher_key(&key));
+            self.key = Some(key);
+        }
+        self.flags = flags;
+
+        // The response itself is protected by the new session
+        let body = self.open(b'C', body)?;
+        if body.len() != LEN_CONNECT_RESPONSE {
+            return Err(PyValueError::new_err("Invalid connect response length"));
+        }
+        Ok(flags)
+    }
+
+    /// Returns true once a session key has been derived, so responses are authenticated.
+    fn has_key(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Protect the body of a message to the decoder for the given opcode.
+    fn seal(&mut self, opcode: u8, body: Vec<u8>) -> Vec<u8> {
+        let cipher_key = match &self.cipher_key {
+            Some(cipher_key) if self.flags & CONNECT_FLAG_ENCRYPTION != 0 && opcode != b'C' => {
+                cipher_key
+            }
+            _ => return body,
+        };
+
+        let nonce = make_session_nonce(SESSION_DIRECTION_TO_DECODER, opcode, self.tx_sequence);
+        self.tx_sequence = self.tx_sequence.wrapping_add(1);
+        encrypt_ascon_with_nonce(&body, &nonce, &cipher_key.0)
+    }
+
+    /// Check and strip the protection of a decoder response body for the given opcode.
+    fn open(&mut self, opcode: u8, mut body: Vec<u8>) -> PyResult<Vec<u8>> {
+        let (key, cipher_key) = match (&self.key, &self.cipher_key) {
+            (Some(key), Some(cipher_key)) => (key, cipher_key),
+            _ => return Ok(body),
+        };
+
+        let sequence = self.rx_sequence;
+        self.rx_sequence = self.rx_sequence.wrapping_add(1);
+
+        // Connect responses are authenticated even in encrypted sessions
+        if self.flags & CONNECT_FLAG_ENCRYPTION != 0 && opcode != b'C' {
+            let nonce = make_session_nonce(SESSION_DIRECTION_TO_HOST, opcode, sequence);
+            return decrypt_ascon_with_nonce(&body, &nonce, &cipher_key.0)
+                .ok_or_else(|| PyValueError::new_err("Invalid encrypted response"));
+        }
+
+        if body.len() < LEN_RESPONSE_MAC {
+            return Err(PyValueError::new_err("Response is missing its MAC"));
+        }
+        let mac = body.split_off(body.len() - LEN_RESPONSE_MAC);
+        let expected = compute_response_mac(key, sequence, opcode, &body);
+        if expected.0[..] != mac[..] {
+            return Err(PyValueError::new_err("Invalid response MAC"));
+        }
+
+        Ok(body)
+    }
+}
+
+impl Session {
+    /// Returns the flags a decoder grants for the requested ones: every known flag, except
+    /// response authentication when encryption (which already authenticates) is requested.
+    fn expected_flags(&self) -> u8 {
+        let mut flags = self.requested_flags
+            & (CONNECT_FLAG_RESPONSE_AUTH | CONNECT_FLAG_ENCRYPTION | CONNECT_FLAG_CHECKSUM);
+        if flags & CONNECT_FLAG_ENCRYPTION != 0 {
+            flags &= !CONNECT_FLAG_RESPONSE_AUTH;
+        }
+        flags
+    }
+}
@@ -0,0 +1,61 @@
+"""
+Challenge a Decoder to prove it was provisioned for a given decoder ID, and report its
+firmware measurement.
+"""
+
+import argparse
+import os
+
+from loguru import logger
+
+from ectf25.utils.decoder import DecoderIntf
+
+
+def main():
+    # Define and parse command line arguments
+    parser = argparse.ArgumentParser(
+        prog="ectf25.tv.attest",
+        description="Verify that a Decoder is genuine and report its firmware measurement",
+    )
+    parser.add_argument(
+        "secrets_file",
+        type=argparse.FileType("rb"),
+        help="Path to the secrets file created by ectf25_design.gen_secrets",
+    )
+    parser.add_argument(
+        "device_id", type=lambda x: int(x, 0), help="Expected Device ID of the Decoder"
+    )
+    parser.add_argument(
+        "port",
+        help="Serial port to the Decoder (see https://rules.ectf.mitre.org/2025/getting_started/boot_reference for platform-specific instructions)",
+    )
+    parser.add_argument(
+        "--firmware",
+        type=argparse.FileType("rb"),
+        help="Built Decoder image to compare the firmware measurement against",
+    )
+    args = parser.parse_args()
+
+    from ectf25_design.attestation import measure_firmware, verify_attestation
+
+    secrets = args.secrets_file.read()
+    expected = measure_firmware(args.firmware.read()) if args.firmware else None
+
+    # Open Decoder interface
+    decoder = DecoderIntf(args.port)
+
+    # Run attest command
+    nonce = os.urandom(16)
+    response = decoder.attest(nonce)
+
+    if not verify_attestation(secrets, args.device_id, nonce, response, expected):
+        logger.error("Attestation failed")
+        raise SystemExit(1)
+
+    measurement = response[4:36]
+    logger.info(f"Firmware measurement: {measurement.hex()}")
+    logger.success("Attestation successful")
+
+
+if __name__ == "__main__":
+    main()
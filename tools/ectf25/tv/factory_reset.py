@@ -0,0 +1,42 @@
+"""
+Erase all standard channel subscriptions from a Decoder.
+"""
+
+import argparse
+
+from loguru import logger
+
+from ectf25.utils.decoder import DecoderIntf
+
+
+def main():
+    # Define and parse command line arguments
+    parser = argparse.ArgumentParser(
+        prog="ectf25.tv.factory_reset",
+        description="Erase all standard channel subscriptions from a Decoder",
+    )
+    parser.add_argument(
+        "reset_file",
+        type=argparse.FileType("rb"),
+        help="Path to the factory reset file created by ectf25_design.gen_factory_reset",
+    )
+    parser.add_argument(
+        "port",
+        help="Serial port to the Decoder (see https://rules.ectf.mitre.org/2025/getting_started/boot_reference for platform-specific instructions)",
+    )
+    args = parser.parse_args()
+
+    # Read factory reset file
+    reset = args.reset_file.read()
+
+    # Open Decoder interface
+    decoder = DecoderIntf(args.port)
+
+    # Run factory reset command
+    decoder.factory_reset(reset)
+
+    logger.success("Factory reset successful")
+
+
+if __name__ == "__main__":
+    main()
@@ -27,17 +27,23 @@ def main():
         "port",
         help="Serial port to the Decoder (see https://rules.ectf.mitre.org/2025/getting_started/boot_reference for platform-specific instructions)",
     )
+    parser.add_argument(
+        "--status",
+        action="store_true",
+        help="Mark subscriptions which end before the last frame decoded since the Decoder booted",
+    )
     args = parser.parse_args()
 
     # Open Decoder interface
     decoder = DecoderIntf(args.port)
 
     # Run the list command
-    subscriptions = decoder.list()
+    subscriptions = decoder.list(status=args.status)
 
     # Print the results
-    for channel, start, end in subscriptions:
-        logger.info(f"Found subscription: Channel {channel} {start}:{end}")
+    for channel, start, end, *status in subscriptions:
+        expired = " (expired)" if status and status[0] else ""
+        logger.info(f"Found subscription: Channel {channel} {start}:{end}{expired}")
 
     logger.success("List successful")
 
@@ -10,9 +10,11 @@ own risk!
 Copyright: Copyright (c) 2025 The MITRE Corporation
 """
 
+import binascii
 from dataclasses import dataclass
 from enum import IntEnum
 import struct
+import time
 from typing import Optional, Iterator
 
 from loguru import logger
@@ -22,6 +24,33 @@ from serial.serialutil import SerialTimeoutException
 MAGIC = b"%"
 BLOCK_LEN = 256
 
+LIST_FLAG_STATUS = 0x01
+LIST_STATUS_EXPIRED = 0x01
+
+CONNECT_FLAG_RESPONSE_AUTH = 0x01
+CONNECT_FLAG_ENCRYPTION = 0x02
+CONNECT_FLAG_CHECKSUM = 0x04
+MAX_RETRANSMITS = 3
+
+DEFAULT_BAUD_RATE = 115200
+SUPPORTED_BAUD_RATES = (115200, 230400, 460800, 921600)
+TELEMETRY_FLAG_RESET = 0x01
+TELEMETRY_CHANNELS = 9
+FRAME_REJECTION_REASONS = (
+    "invalid_frame",
+    "no_subscription",
+    "outside_window",
+    "stale_timestamp",
+    "invalid_picture",
+)
+
+LEN_CRASH_FILE = 24
+
+MAX_BATCH_FRAMES = 7
+BATCH_STATUS_OK = 0x00
+
+BAUD_CONFIRM_TIMEOUT = 2.0  # seconds to wait for the Decoder to confirm a new baud rate
+
 
 class Opcode(IntEnum):
     """Enum class for use in device output processing."""
@@ -32,9 +61,34 @@ class Opcode(IntEnum):
     ACK = 0x41  # A
     DEBUG = 0x47  # G
     ERROR = 0x45  # E
+    ATTEST = 0x54  # T
+    CONNECT = 0x43  # C
+    NACK = 0x4E  # N
+    BAUD = 0x42  # B
+    DECODE_BATCH = 0x46  # F
+    TELEMETRY = 0x4D  # M
+    FACTORY_RESET = 0x52  # R
+    CRASH_LOG = 0x50  # P
+
+
+NACK_MSGS = {Opcode.DEBUG, Opcode.ACK, Opcode.NACK}
 
+# Level prefixes on DEBUG messages from a Decoder built with the debug-log feature
+LOG_LEVELS = {b"E ": "ERROR", b"W ": "WARNING", b"I ": "INFO", b"D ": "DEBUG"}
 
-NACK_MSGS = {Opcode.DEBUG, Opcode.ACK}
+
+def log_debug_msg(body: bytes):
+    """Log the body of a DEBUG message at the level it was sent with"""
+    level = LOG_LEVELS.get(body[:2])
+    if level is None:
+        logger.info(f"Got DEBUG: {repr(body)}")
+    else:
+        text = body[2:].decode(errors="backslashreplace")
+        logger.log(level, f"Decoder: {text}")
+
+
+class ChecksumError(Exception):
+    pass
 
 
 @dataclass
@@ -45,25 +99,35 @@ class MessageHdr:
     len: int
 
     @classmethod
-    def parse(cls, stream: bytes) -> tuple["MessageHdr", bytes]:
+    def parse(cls, stream: bytes, checksums: bool = False) -> tuple["MessageHdr", bytes]:
         """Try to parse a stream of bytes into a MessageHdr
 
         :param stream: Stream of bytes to parse
+        :param checksums: Whether the header carries a CRC-16 trailer
 
         :returns: A tuple with the first parsable MesssageHdr and the remaining bytes
+        :raises ChecksumError: If the header's checksum does not match
         """
         pre, magic, remainder = stream.partition(MAGIC)
 
         if magic != b"%":
             raise ValueError("No magic found")
 
-        hdr, remainder = remainder[:3], remainder[3:]
-        opc, ln = struct.unpack("<BH", hdr)
+        hdr_len = 5 if checksums else 3
+        hdr, remainder = remainder[:hdr_len], remainder[hdr_len:]
+        opc, ln = struct.unpack("<BH", hdr[:3])
+        if checksums:
+            (crc,) = struct.unpack("<H", hdr[3:])
+            if crc != binascii.crc_hqx(hdr[:3], 0xFFFF):
+                raise ChecksumError("Bad header checksum")
         return cls(Opcode(opc), ln), remainder
 
-    def pack(self) -> bytes:
-        """Pack the MessageHdr into bytes"""
-        return MAGIC + struct.pack("<BH", self.opcode, self.len)
+    def pack(self, checksums: bool = False) -> bytes:
+        """Pack the MessageHdr into bytes, with a CRC-16 trailer if checksums are enabled"""
+        hdr = struct.pack("<BH", self.opcode, self.len)
+        if checksums:
+            hdr += struct.pack("<H", binascii.crc_hqx(hdr, 0xFFFF))
+        return MAGIC + hdr
 
 
 @dataclass
@@ -82,12 +146,16 @@ class Message:
         """Pack the Message into bytes"""
         return self.hdr.pack() + self.body
 
-    def packets(self) -> Iterator[bytes]:
+    def packets(self, checksums: bool = False) -> Iterator[bytes]:
         """An iterator that chunks the message into blocks to send to the Decoder. An
-        ACK is expected from the Decoder after each block"""
-        yield self.hdr.pack()
+        ACK is expected from the Decoder after each block. With checksums enabled, each
+        block is followed by its CRC-32"""
+        yield self.hdr.pack(checksums)
         for i in range(0, len(self.body), BLOCK_LEN):
-            yield self.body[i : i + BLOCK_LEN]
+            block = self.body[i : i + BLOCK_LEN]
+            if checksums:
+                block += struct.pack("<I", binascii.crc32(block))
+            yield block
 
     def is_ack(self) -> bool:
         """Returns whether the message is an ACK"""
@@ -105,15 +173,18 @@ class DecoderIntf:
     """
 
     ACK = Message(Opcode.ACK, b"")
+    NACK = Message(Opcode.NACK, b"")
 
     def __init__(self, port, **serial_kwargs):
         """
         :param port: Serial port to the Decoder
         :param serial_kwargs: Args to pass to the serial interface construction
         """
-        self.ser = Serial(baudrate=115200, **serial_kwargs)
+        self.ser = Serial(baudrate=DEFAULT_BAUD_RATE, **serial_kwargs)
         self.ser.port = port
         self.stream = b""
+        self.session = None
+        self.checksums = False
 
     def _open(self):
         """Open the serial connection if not already opened"""
@@ -137,6 +208,37 @@ class DecoderIntf:
             raise DecoderError(f"Bad decode response {resp}")
         return resp.body
 
+    def decode_batch(self, frames: list[bytes]) -> list[Optional[bytes]]:
+        """Decode several frames in order with a single message
+
+        :param frames: Up to MAX_BATCH_FRAMES encoded frames
+        :returns: The decoded frame for each input frame, or None where it failed to decode
+        :raises DecoderError: Error on batch failure
+        """
+        # send batch decode message
+        msg = Message(Opcode.DECODE_BATCH, b"".join(frames))
+        self.send_msg(msg)
+
+        # receive response
+        resp = self.get_msg()
+        if resp.opcode != Opcode.DECODE_BATCH:
+            raise DecoderError(f"Bad batch decode response {resp}")
+
+        # unpack a status, length and picture for each frame
+        decoded = []
+        body = resp.body
+        for _ in frames:
+            if len(body) < 2:
+                raise DecoderError(f"Bad batch decode response {resp}")
+            status, length, body = body[0], body[1], body[2:]
+            if len(body) < length:
+                raise DecoderError(f"Bad batch decode response {resp}")
+            picture, body = body[:length], body[length:]
+            decoded.append(picture if status == BATCH_STATUS_OK else None)
+        if body:
+            raise DecoderError(f"Bad batch decode response {resp}")
+        return decoded
+
     def subscribe(self, subscription: bytes):
         """Subscribe the Decoder to a new subscription
 
@@ -153,15 +255,35 @@ class DecoderIntf:
         if resp != Message(Opcode.SUBSCRIBE, b""):
             raise DecoderError(f"Bad subscribe response {resp}")
 
-    def list(self) -> list[tuple[int, int, int]]:
+    def factory_reset(self, reset: bytes):
+        """Erase all standard channel subscriptions from the Decoder
+
+        :param reset: Content of factory reset file created by
+            ectf25_design.gen_factory_reset
+        :raises DecoderError: Error on factory reset failure
+        """
+        # send factory reset message
+        msg = Message(Opcode.FACTORY_RESET, reset)
+        self.send_msg(msg)
+
+        # receive response
+        resp = self.get_msg()
+        if resp != Message(Opcode.FACTORY_RESET, b""):
+            raise DecoderError(f"Bad factory reset response {resp}")
+
+    def list(self, status: bool = False) -> list[tuple]:
         """List the subscribed channels of a Decoder
 
+        :param status: Also request a status byte for each entry, reporting whether the
+            entry has expired. An entry has expired once it ends before the last frame the
+            Decoder decoded since it booted, so nothing is reported as expired until a frame
+            has been decoded after a restart
         :returns: A list of tuples containing the subscribed channels and start and end
-            timestamps
+            timestamps, plus whether the entry is expired if `status` is set
         :raises DecoderError: Error on list failure
         """
         # send list message
-        msg = Message(Opcode.LIST, b"")
+        msg = Message(Opcode.LIST, bytes([LIST_FLAG_STATUS]) if status else b"")
         self.send_msg(msg)
 
         # receive response
@@ -175,7 +297,8 @@ class DecoderIntf:
         logger.debug(f"Reported {nchannels} subscribed channels")
 
         # check for correct channels body size
-        sz = struct.calcsize("<IQQ")
+        fmt = "<IQQB" if status else "<IQQ"
+        sz = struct.calcsize(fmt)
         expected = sz * nchannels
         if expected != len(body):
             raise DecoderError(
@@ -186,47 +309,273 @@ class DecoderIntf:
         channels = []
         for _ in range(nchannels):
             cbody, body = body[:sz], body[sz:]
-            channel, start, end = struct.unpack("<IQQ", cbody)
-            logger.debug(f"Found subscription for {channel} from {start} to {end}")
-            channels.append((channel, start, end))
+            if status:
+                channel, start, end, flags = struct.unpack(fmt, cbody)
+                expired = bool(flags & LIST_STATUS_EXPIRED)
+                logger.debug(
+                    f"Found subscription for {channel} from {start} to {end}"
+                    f"{' (expired)' if expired else ''}"
+                )
+                channels.append((channel, start, end, expired))
+            else:
+                channel, start, end = struct.unpack(fmt, cbody)
+                logger.debug(f"Found subscription for {channel} from {start} to {end}")
+                channels.append((channel, start, end))
 
         return channels
 
+    def telemetry(self, reset: bool = False) -> dict:
+        """Read the Decoder's health counters
+
+        The Decoder only answers in an encrypted session, so call `connect` with
+        CONNECT_FLAG_ENCRYPTION first.
+
+        :param reset: Also reset the counters after reading them
+        :returns: A dict of counters, with frames decoded per channel under "frames_decoded"
+            and frames rejected per reason under "frames_rejected"
+        :raises DecoderError: Error on telemetry failure
+        """
+        # send telemetry message
+        msg = Message(Opcode.TELEMETRY, bytes([TELEMETRY_FLAG_RESET]) if reset else b"")
+        self.send_msg(msg)
+
+        # receive response
+        resp = self.get_msg()
+        if resp.opcode != Opcode.TELEMETRY:
+            raise DecoderError(f"Bad telemetry response {resp}")
+
+        # unpack counters
+        fmt = f"<I{2 * TELEMETRY_CHANNELS}I{1 + len(FRAME_REJECTION_REASONS)}I4I"
+        if len(resp.body) != struct.calcsize(fmt):
+            raise DecoderError(f"Bad telemetry response {resp}")
+        num_channels, *counters = struct.unpack(fmt, resp.body)
+        channels, counters = counters[: 2 * TELEMETRY_CHANNELS], counters[2 * TELEMETRY_CHANNELS :]
+        untracked, counters = counters[0], counters[1:]
+        rejected = counters[: len(FRAME_REJECTION_REASONS)]
+        accepted, sub_rejected, integrity_failures, rng_reseeds = counters[
+            len(FRAME_REJECTION_REASONS) :
+        ]
+        return {
+            "frames_decoded": {
+                channels[2 * i]: channels[2 * i + 1]
+                for i in range(min(num_channels, TELEMETRY_CHANNELS))
+            },
+            "frames_decoded_untracked": untracked,
+            "frames_rejected": dict(zip(FRAME_REJECTION_REASONS, rejected)),
+            "subscriptions_accepted": accepted,
+            "subscriptions_rejected": sub_rejected,
+            "integrity_failures": integrity_failures,
+            "rng_reseeds": rng_reseeds,
+        }
+
+    def crash_log(self) -> tuple[int, list[tuple[str, int, int]]]:
+        """Read the panic locations recorded by a Decoder built with the crash-log feature
+
+        :returns: The total number of panics recorded, and the most recent ones as
+            (source path, line, column), oldest first. Source paths are truncated to
+            their last 24 bytes.
+        :raises DecoderError: Error on crash log failure
+        """
+        # send crash log message
+        msg = Message(Opcode.CRASH_LOG, b"")
+        self.send_msg(msg)
+
+        # receive response
+        resp = self.get_msg()
+        if resp.opcode != Opcode.CRASH_LOG:
+            raise DecoderError(f"Bad crash log response {resp}")
+
+        # unpack records
+        fmt = f"<II{LEN_CRASH_FILE}s"
+        sz = struct.calcsize(fmt)
+        if len(resp.body) < 4 or (len(resp.body) - 4) % sz != 0:
+            raise DecoderError(f"Bad crash log response {resp}")
+        count = struct.unpack("<I", resp.body[:4])[0]
+        records = []
+        for line, column, file in struct.iter_unpack(fmt, resp.body[4:]):
+            records.append((file.rstrip(b"\x00").decode(errors="replace"), line, column))
+        return count, records
+
+    def attest(self, nonce: bytes) -> bytes:
+        """Challenge the Decoder to attest to its identity and firmware
+
+        :param nonce: A fresh 16-byte nonce
+        :returns: The Decoder's attestation response, to be checked with
+            ectf25_design.attestation.verify_attestation
+        :raises DecoderError: Error on attestation failure
+        """
+        # send attest message
+        msg = Message(Opcode.ATTEST, nonce)
+        self.send_msg(msg)
+
+        # receive response
+        resp = self.get_msg()
+        if resp.opcode != Opcode.ATTEST:
+            raise DecoderError(f"Bad attest response {resp}")
+        return resp.body
+
+    def connect(self, session) -> int:
+        """Negotiate optional protocol features with the Decoder
+
+        :param session: An ectf25_design.session.Session for the Decoder
+        :returns: The feature flags accepted by the Decoder
+        :raises DecoderError: Error on connect failure
+        """
+        # messages are only protected once the new session is accepted
+        self.session = None
+
+        # send connect message
+        msg = Message(Opcode.CONNECT, session.connect_request())
+        self.send_msg(msg)
+
+        # receive response
+        resp = self.get_msg()
+        if resp.opcode != Opcode.CONNECT:
+            raise DecoderError(f"Bad connect response {resp}")
+        try:
+            flags = session.accept(resp.body)
+        except ValueError as e:
+            raise DecoderError(f"Bad connect response: {e}")
+
+        # never fall back to unauthenticated responses once authentication was requested
+        if session.requested_flags & CONNECT_FLAG_RESPONSE_AUTH and not session.has_key():
+            raise DecoderError("Connect response did not establish an authenticated session")
+        # nor to plaintext once encryption was requested
+        if session.requested_flags & CONNECT_FLAG_ENCRYPTION and not (
+            flags & CONNECT_FLAG_ENCRYPTION and session.has_key()
+        ):
+            raise DecoderError("Connect response did not establish an encrypted session")
+
+        self.session = session if flags else None
+        self.checksums = bool(flags & CONNECT_FLAG_CHECKSUM)
+        return flags
+
+    def set_baud_rate(self, baud_rate: int):
+        """Switch the connection to the Decoder to a different baud rate
+
+        The Decoder confirms the rate, then both sides switch and the host sends an empty
+        BAUD message at the new rate. If the Decoder does not ACK it, both sides fall back
+        to the previous rate.
+
+        :param baud_rate: One of SUPPORTED_BAUD_RATES
+        :raises DecoderError: If the Decoder refuses the rate or the switch fails
+        """
+        # send baud rate message
+        msg = Message(Opcode.BAUD, struct.pack("<I", baud_rate))
+        self.send_msg(msg)
+
+        # receive response
+        resp = self.get_msg()
+        if resp != msg:
+            raise DecoderError(f"Bad baud rate response {resp}")
+
+        # switch, giving the Decoder a moment to do the same
+        old_baud_rate, old_timeout = self.ser.baudrate, self.ser.timeout
+        self.ser.flush()
+        self.ser.baudrate = baud_rate
+        self.ser.timeout = BAUD_CONFIRM_TIMEOUT
+        self.stream = b""
+        self.ser.reset_input_buffer()
+        time.sleep(0.01)
+
+        # confirm the new rate
+        try:
+            self.ser.write(Message(Opcode.BAUD, b"").pack(self.checksums))
+            confirmed = self.get_ack()
+        except (DecoderError, SerialTimeoutException):
+            confirmed = False
+        finally:
+            self.ser.timeout = old_timeout
+        if not confirmed:
+            self.ser.baudrate = old_baud_rate
+            raise DecoderError(f"Baud rate not confirmed, staying at {old_baud_rate}")
+
     def send_ack(self):
         """Send an ACK to the Decoder"""
         self._open()
-        self.ser.write(self.ACK.pack())
+        self.ser.write(self.ACK.pack(self.checksums))
 
-    def get_ack(self):
-        """Get an expected ACK from the Decoder
+    def send_nack(self):
+        """Discard any pending input and ask the Decoder to retransmit"""
+        self._open()
+        self.stream = b""
+        self.ser.reset_input_buffer()
+        self.ser.write(self.NACK.pack(self.checksums))
 
+    def get_ack(self) -> bool:
+        """Get an expected ACK or NACK from the Decoder
+
+        :returns: True for an ACK, False for a NACK
         :raises DecoderError: Non-ACK response was received (other than DEBUGs)
         """
-        msg = self.get_msg()
-        if msg != self.ACK:
-            logger.error(f"Got bad ACK {msg}")
-            raise DecoderError(f"Got bad ACK {msg}")
+        msg = self.get_msg(nack=False)
+        if msg == self.ACK:
+            return True
+        if msg == self.NACK:
+            return False
+        logger.error(f"Got bad ACK {msg}")
+        raise DecoderError(f"Got bad ACK {msg}")
 
     def try_parse(self) -> Optional[MessageHdr]:
         """Try to parse the input stream into a MessageHdr
 
         :returns: The MessageHdr if the parse was successful, None otherwise
+        :raises ChecksumError: If a complete header was found with a bad checksum
         """
         try:
-            hdr, self.stream = MessageHdr.parse(self.stream)
+            hdr, self.stream = MessageHdr.parse(self.stream, self.checksums)
         except (ValueError, struct.error):
             return None
         logger.debug(f"Found header {hdr}")
         return hdr
 
-    def get_raw_msg(self) -> Message:
+    def read_exact(self, n: int) -> bytes:
+        """Read exactly n bytes from the Decoder
+
+        :raises SerialTimeoutException: If the Decoder stops sending
+        """
+        data = b""
+        while remaining := n - len(data):
+            b = self.ser.read(remaining)
+            if b == b'':
+                raise SerialTimeoutException('Read timeout')
+            data += b
+        return data
+
+    def read_block(self, size: int) -> Optional[bytes]:
+        """Read a block of the message body, checking its CRC-32 if checksums are enabled
+
+        :returns: The block, or None if its checksum did not match
+        """
+        if not self.checksums:
+            return self.read_exact(size)
+        data = self.read_exact(size + 4)
+        block, (crc,) = data[:size], struct.unpack("<I", data[size:])
+        if crc != binascii.crc32(block):
+            return None
+        return block
+
+    def get_raw_msg(self, nack: bool = True) -> Message:
         """Get a message, blocking until full message received
 
+        :param nack: Whether to NACK a corrupted header. Should be False while waiting
+            for an ACK, as the Decoder is not expecting a NACK then
         :returns: Message received by Decoder
         :raises: DecoderError if unexpected behavior encountered
         """
         self._open()
-        while (hdr := self.try_parse()) is None:
+        while True:
+            try:
+                hdr = self.try_parse()
+            except ChecksumError:
+                logger.warning("Discarding header with bad checksum")
+                if nack:
+                    self.send_nack()
+                else:
+                    self.stream = b""
+                continue
+            if hdr is not None:
+                break
             b = self.ser.read(1)
             if b == b'':
                 raise SerialTimeoutException('Read timeout')
@@ -237,12 +586,17 @@ class DecoderIntf:
         remaining = hdr.len
         body = b""
         while remaining > 0:
-            block = b""
-            while block_remaining := min(BLOCK_LEN, remaining) - len(block):
-                b = self.ser.read(block_remaining)
-                if b == b'':
-                    raise SerialTimeoutException('Read timeout')
-                block += b
+            size = min(BLOCK_LEN, remaining)
+            for _ in range(MAX_RETRANSMITS + 1):
+                block = self.read_block(size)
+                if block is not None or hdr.opcode in NACK_MSGS:
+                    break
+                logger.warning("Got block with bad checksum, requesting retransmit")
+                self.send_nack()
+            else:
+                raise DecoderError("Too many corrupted blocks")
+            if block is None:
+                raise DecoderError(f"Corrupted block in unacknowledged {hdr.opcode.name}")
             # Don't ACK an ACK or a debug message
             if hdr.opcode not in NACK_MSGS:
                 self.send_ack()
@@ -253,19 +607,25 @@ class DecoderIntf:
         logger.debug(f"Got message {msg}")
         return msg
 
-    def get_msg(self) -> Message:
+    def get_msg(self, nack: bool = True) -> Message:
         """Get a message, handling DEBUG and ERROR messages
 
+        :param nack: Whether to NACK a corrupted header
         :returns: Message received by Decoder, filtering DEBUGs
         :raises DecoderError: If unexpected behavior or ERROR message encountered
         """
         while True:
-            msg = self.get_raw_msg()
+            msg = self.get_raw_msg(nack)
+            if self.session is not None and msg.opcode not in (Opcode.ACK, Opcode.NACK):
+                try:
+                    msg = Message(msg.opcode, self.session.open(msg.opcode, msg.body))
+                except ValueError as e:
+                    raise DecoderError(f"Bad authenticated response: {e}")
             if msg.opcode == Opcode.ERROR:
                 raise DecoderError(f"Decoder returned ERROR: {repr(msg.body)}")
             if msg.opcode != Opcode.DEBUG:
                 return msg
-            logger.info(f"Got DEBUG: {repr(msg.body)}")
+            log_debug_msg(msg.body)
 
     def send_msg(self, msg: Message):
         """Send a message to the Decoder
@@ -274,7 +634,14 @@ class DecoderIntf:
         :raises DecoderError: If unexpected behavior or ERROR message encountered
         """
         self._open()
-        for packet in msg.packets():
-            logger.debug(f"Sending packet {packet}")
-            self.ser.write(packet)
-            self.get_ack()
+        if self.session is not None:
+            msg = Message(msg.opcode, self.session.seal(msg.opcode, msg.body))
+        for packet in msg.packets(self.checksums):
+            for _ in range(MAX_RETRANSMITS + 1):
+                logger.debug(f"Sending packet {packet}")
+                self.ser.write(packet)
+                if self.get_ack():
+                    break
+                logger.warning("Got NACK, retransmitting packet")
+            else:
+                raise DecoderError("Decoder rejected packet too many times")
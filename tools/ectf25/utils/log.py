@@ -0,0 +1,40 @@
+"""
+Print log messages from a Decoder built with the debug-log feature.
+"""
+
+import argparse
+
+from loguru import logger
+
+from ectf25.utils.decoder import DecoderIntf, Opcode, log_debug_msg
+
+
+def main():
+    # Define and parse command line arguments
+    parser = argparse.ArgumentParser(
+        prog="ectf25.utils.log",
+        description="Print log messages sent by a Decoder built with the debug-log feature",
+    )
+    parser.add_argument(
+        "port",
+        help="Serial port to the Decoder (see https://rules.ectf.mitre.org/2025/getting_started/boot_reference for platform-specific instructions)",
+    )
+    args = parser.parse_args()
+
+    # Open Decoder interface
+    decoder = DecoderIntf(args.port)
+
+    # Print DEBUG messages forever, ignoring anything else
+    try:
+        while True:
+            msg = decoder.get_raw_msg()
+            if msg.opcode == Opcode.DEBUG:
+                log_debug_msg(msg.body)
+            else:
+                logger.debug(f"Ignoring {msg}")
+    except KeyboardInterrupt:
+        pass
+
+
+if __name__ == "__main__":
+    main()